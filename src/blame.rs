@@ -0,0 +1,117 @@
+use crate::git;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const UNCOMMITTED: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Debug, Default, Clone)]
+pub struct Commit {
+    pub hash: String,
+    pub author: String,
+    /// Seconds since the epoch
+    pub time: i64,
+    pub summary: String,
+}
+
+impl Commit {
+    pub fn is_committed(&self) -> bool {
+        self.hash != UNCOMMITTED
+    }
+
+    pub fn short_hash(&self) -> &str {
+        &self.hash[..7]
+    }
+}
+
+/// The commit that last touched each line of a file
+#[derive(Debug, Default, Clone)]
+pub struct Blame {
+    dir: PathBuf,
+    commits: HashMap<String, Commit>,
+    lines: Vec<String>,
+}
+
+impl Blame {
+    pub fn load(path: &Path) -> Option<Self> {
+        let dir = path.parent()?;
+        let name = path.file_name()?.to_str()?;
+        let out = git::run_git(dir, &["blame", "--porcelain", "--", name])?;
+        let mut blame = Blame {
+            dir: dir.to_path_buf(),
+            ..Default::default()
+        };
+        let mut hash = String::new();
+        for l in String::from_utf8_lossy(&out).lines() {
+            if l.starts_with('\t') {
+                blame.lines.push(hash.clone());
+                continue;
+            }
+            let (key, val) = l.split_once(' ').unwrap_or((l, ""));
+            if key.len() == 40 && key.bytes().all(|b| b.is_ascii_hexdigit()) {
+                hash = key.to_string();
+                blame.commits.entry(hash.clone()).or_insert_with(|| Commit {
+                    hash: hash.clone(),
+                    ..Default::default()
+                });
+            } else if let Some(c) = blame.commits.get_mut(&hash) {
+                match key {
+                    "author" => c.author = val.to_string(),
+                    "author-time" => c.time = val.parse().unwrap_or(0),
+                    "summary" => c.summary = val.to_string(),
+                    _ => (),
+                }
+            }
+        }
+        Some(blame)
+    }
+
+    pub fn commit(&self, line: usize) -> Option<&Commit> {
+        self.commits.get(self.lines.get(line)?)
+    }
+
+    /// The text shown next to `line`, e.g. "1a2b3c4 Jane Doe     3 days ago"
+    pub fn label(&self, line: usize) -> Option<String> {
+        let c = self.commit(line)?;
+        if !c.is_committed() {
+            return Some("Not committed yet".to_string());
+        }
+        Some(format!(
+            "{} {:<12.12} {}",
+            c.short_hash(),
+            c.author,
+            relative_date(c.time)
+        ))
+    }
+
+    /// The full commit message of `c`
+    pub fn message(&self, c: &Commit) -> String {
+        git::run_git(&self.dir, &["show", "-s", "--format=%B", &c.hash])
+            .map(|out| String::from_utf8_lossy(&out).trim_end().to_string())
+            .unwrap_or_else(|| c.summary.clone())
+    }
+
+    /// The changes introduced by `c`
+    pub fn show(&self, c: &Commit) -> Option<String> {
+        git::run_git(&self.dir, &["show", &c.hash]).map(|out| String::from_utf8_lossy(&out).into())
+    }
+}
+
+pub fn relative_date(time: i64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(time);
+    let secs = (now - time).max(0);
+    let (n, unit) = match secs {
+        s if s < 60 => return "just now".to_string(),
+        s if s < 3600 => (s / 60, "minute"),
+        s if s < 86400 => (s / 3600, "hour"),
+        s if s < 86400 * 30 => (s / 86400, "day"),
+        s if s < 86400 * 365 => (s / (86400 * 30), "month"),
+        s => (s / (86400 * 365), "year"),
+    };
+    format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" })
+}
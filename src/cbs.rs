@@ -1,6 +1,9 @@
-use crate::state::STATE;
+use crate::{commands, fbr, git, gutter, lsp, state::STATE, tasks};
 use fltk::{enums::*, prelude::*, *};
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 fn nfc_get_file(mode: dialog::NativeFileChooserType) -> PathBuf {
     let mut nfc = dialog::NativeFileChooser::new(mode);
@@ -8,17 +11,41 @@ fn nfc_get_file(mode: dialog::NativeFileChooserType) -> PathBuf {
     nfc.filename()
 }
 
-fn find() {
+pub fn find() {
     let mut dlg: window::Window = app::widget_from_id("find").unwrap();
     let main_win = app::first_window().unwrap();
     dlg.resize(main_win.x() + main_win.w() - 300, dlg.y() + 30, 300, 50);
     dlg.show();
 }
 
-fn replace() {
+pub fn replace() {
     let mut dlg: window::Window = app::widget_from_id("replace").unwrap();
     let main_win = app::first_window().unwrap();
-    dlg.resize(main_win.x() + main_win.w() - 300, dlg.y() + 30, 300, 80);
+    dlg.resize(main_win.x() + main_win.w() - 360, dlg.y() + 30, 360, 80);
+    dlg.show();
+}
+
+pub fn goto_line() {
+    let mut dlg: window::Window = app::widget_from_id("goto_line").unwrap();
+    let main_win = app::first_window().unwrap();
+    dlg.resize(
+        main_win.x() + (main_win.w() - 300) / 2,
+        main_win.y() + 30,
+        300,
+        50,
+    );
+    dlg.show();
+}
+
+pub fn quick_open() {
+    let mut dlg: window::Window = app::widget_from_id("quick_open").unwrap();
+    let main_win = app::first_window().unwrap();
+    dlg.resize(
+        main_win.x() + (main_win.w() - 600) / 2,
+        main_win.y() + 30,
+        600,
+        400,
+    );
     dlg.show();
 }
 
@@ -32,135 +59,199 @@ pub fn editor_cb(_e: &mut text::TextEditor) {
     app::add_timeout3(0.01, |_| STATE.with(|s| s.was_modified(true)));
 }
 
+/// Shows `path` in the browser once it was created, or why it couldn't be
+fn created(path: &Path, res: std::io::Result<()>) -> bool {
+    if let Err(e) = res {
+        dialog::alert_default(&format!("Couldn't create {}: {}", path.display(), e));
+        return false;
+    }
+    if let Some(dir) = path.parent() {
+        fbr::refresh(dir);
+    }
+    fbr::reveal(path);
+    true
+}
+
 pub fn new_file() {
     let dlg = dialog::input_default("Enter file name", "");
-    if let Some(f) = dlg {
-        fs::File::create(f).ok();
+    if let Some(f) = dlg.filter(|f| !f.trim().is_empty()) {
+        let path = fbr::selected_dir().join(f.trim());
+        // never truncate an existing file
+        let res = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map(|_| ());
+        if created(&path, res) {
+            STATE.with(move |s| s.append(Some(path.clone())));
+        }
     }
 }
 
 pub fn new_dir() {
     let dlg = dialog::input_default("Enter directory name", "");
-    if let Some(f) = dlg {
-        fs::create_dir(f).ok();
+    if let Some(f) = dlg.filter(|f| !f.trim().is_empty()) {
+        let path = fbr::selected_dir().join(f.trim());
+        created(&path, fs::create_dir(&path));
     }
 }
 
-pub fn menu_cb(m: &mut impl MenuExt) {
-    if let Ok(mpath) = m.item_pathname(None) {
-        match mpath.as_str() {
-            "&File/New File...\t" => {
-                new_file();
-            }
-            "&File/New Dir...\t" => {
-                new_dir();
-            }
-            "&File/Open...\t" => {
-                let c = nfc_get_file(dialog::NativeFileChooserType::BrowseFile);
-                if c.exists() {
-                    STATE.with(move |s| {
-                        s.append(Some(c.canonicalize().unwrap()));
-                    });
-                }
-            }
-            "&File/Save\t" => {
-                STATE.with(|s| {
-                    if let Some(id) = s.current_id() {
-                        let e = s.map.get(&id).unwrap();
-                        let modified = e.modified;
-                        if let Some(current_file) = e.current_file.as_ref() {
-                            if modified && current_file.exists() {
-                                fs::write(current_file, e.buf.text()).ok();
-                                s.was_modified(false);
-                            }
-                        }
-                    }
-                });
-            }
-            "&File/Save as...\t" => {
-                let c = nfc_get_file(dialog::NativeFileChooserType::BrowseSaveFile);
-                if c.exists() {
-                    STATE.with(move |s| {
-                        if let Some(buf) = s.buf().as_ref() {
-                            fs::write(&c, buf.text()).expect("Failed to write to file!");
-                            s.was_modified(false);
-                        }
-                    });
-                }
-            }
-            "&File/Save All\t" => {
-                STATE.with(|s| {
-                    for v in s.map.values_mut() {
-                        if v.modified && v.current_file.as_ref().unwrap().exists() {
-                            fs::write(v.current_file.as_ref().unwrap(), v.buf.text()).ok();
-                            v.modified = true;
-                        }
-                    }
-                });
-            }
-            "&File/Quit\t" => app::quit(),
-            "/Undo\t" | "&Edit/Undo\t" => STATE.with(|s| {
-                if let Some(e) = s.current_editor() {
-                    e.undo()
-                }
-            }),
-            "/Redo\t" | "&Edit/Redo\t" => STATE.with(|s| {
-                if let Some(e) = s.current_editor() {
-                    e.redo()
-                }
-            }),
-            "/Cut\t" | "&Edit/Cut\t" => STATE.with(|s| {
-                if let Some(e) = s.current_editor() {
-                    e.cut()
-                }
-            }),
-            "/Copy\t" | "&Edit/Copy\t" => STATE.with(|s| {
-                if let Some(e) = s.current_editor() {
-                    e.copy()
-                }
-            }),
-            "/Paste\t" | "&Edit/Paste\t" => STATE.with(|s| {
-                if let Some(e) = s.current_editor() {
-                    e.paste()
-                }
-            }),
-            "/Find\t" | "&Edit/Find\t" => find(),
-            "/Replace\t" | "&Edit/Replace\t" => replace(),
-            "&View/File browser\t" => {
-                let mut item = m.at(m.value()).unwrap();
-                let fbr: group::Group = app::widget_from_id("fbr_group").unwrap();
-                let mut parent = group::Flex::from_dyn_widget(&fbr.parent().unwrap()).unwrap();
-                if !item.value() {
-                    parent.fixed(&fbr, 1);
-                    item.clear();
-                } else {
-                    parent.fixed(&fbr, 180);
-                    item.set();
+pub fn open() {
+    let c = nfc_get_file(dialog::NativeFileChooserType::BrowseFile);
+    if c.exists() {
+        STATE.with(move |s| {
+            s.append(Some(c.canonicalize().unwrap()));
+        });
+    }
+}
+
+pub fn save() {
+    STATE.with(|s| {
+        if let Some(id) = s.current_id() {
+            let e = s.map.get(&id).unwrap();
+            let modified = e.modified;
+            if let Some(current_file) = e.current_file.as_ref() {
+                if modified && current_file.exists() {
+                    fs::write(current_file, e.buf.text()).ok();
+                    s.was_modified(false);
                 }
-                app::redraw();
             }
-            "&View/Terminal\t" => {
-                let mut item = m.at(m.value()).unwrap();
-                let term: group::Group = app::widget_from_id("term_group").unwrap();
-                let mut parent = group::Flex::from_dyn_widget(&term.parent().unwrap()).unwrap();
-                if !item.value() {
-                    parent.fixed(&term, 1);
-                    item.clear();
-                } else {
-                    parent.fixed(&term, 160);
-                    item.set();
-                }
-                app::redraw();
+        }
+    });
+    if let Some(p) = STATE.with(|s| s.current_file()) {
+        lsp::saved(&p);
+        tasks::saved(&p);
+    }
+    git::refresh();
+}
+
+pub fn save_as() {
+    let c = nfc_get_file(dialog::NativeFileChooserType::BrowseSaveFile);
+    if c.exists() {
+        STATE.with(move |s| {
+            if let Some(buf) = s.buf().as_ref() {
+                fs::write(&c, buf.text()).expect("Failed to write to file!");
+                s.was_modified(false);
             }
-            "&Help/About\t" => {
-                dialog::message_title("About");
-                dialog::message_default("A minimal text editor written using fltk-rs!")
+        });
+    }
+    git::refresh();
+}
+
+pub fn save_all() {
+    STATE.with(|s| {
+        for v in s.map.values_mut() {
+            if v.modified && v.current_file.as_ref().unwrap().exists() {
+                fs::write(v.current_file.as_ref().unwrap(), v.buf.text()).ok();
+                v.modified = true;
             }
-            _ => unreachable!(),
         }
+    });
+    git::refresh();
+}
+
+pub fn quit() {
+    app::quit();
+}
+
+pub fn undo() {
+    STATE.with(|s| {
+        if let Some(e) = s.editable_editor() {
+            e.undo()
+        }
+    });
+}
+
+pub fn redo() {
+    STATE.with(|s| {
+        if let Some(e) = s.editable_editor() {
+            e.redo()
+        }
+    });
+}
+
+pub fn cut() {
+    STATE.with(|s| {
+        if let Some(e) = s.editable_editor() {
+            e.cut()
+        }
+    });
+}
+
+pub fn copy() {
+    STATE.with(|s| {
+        if let Some(e) = s.current_editor() {
+            e.copy()
+        }
+    });
+}
+
+pub fn paste() {
+    STATE.with(|s| {
+        if let Some(e) = s.editable_editor() {
+            e.paste()
+        }
+    });
+}
+
+pub fn command_palette() {
+    let mut dlg: window::Window = app::widget_from_id("command_palette").unwrap();
+    let main_win = app::first_window().unwrap();
+    dlg.resize(
+        main_win.x() + (main_win.w() - 600) / 2,
+        main_win.y() + 30,
+        600,
+        300,
+    );
+    dlg.show();
+}
+
+pub fn toggle_fbr() {
+    let fbr: group::Group = app::widget_from_id("fbr_group").unwrap();
+    let mut parent = group::Flex::from_dyn_widget(&fbr.parent().unwrap()).unwrap();
+    let show = fbr.w() <= 1;
+    parent.fixed(&fbr, if show { 180 } else { 1 });
+    commands::set_checked("view.file_browser", show);
+    app::redraw();
+}
+
+pub fn show_panel() {
+    let panel: group::Tabs = app::widget_from_id("panel").unwrap();
+    if panel.h() <= 1 {
+        toggle_panel();
+    }
+}
+
+pub fn toggle_panel() {
+    let panel: group::Tabs = app::widget_from_id("panel").unwrap();
+    let mut parent = group::Flex::from_dyn_widget(&panel.parent().unwrap()).unwrap();
+    let show = panel.h() <= 1;
+    parent.fixed(&panel, if show { 160 } else { 1 });
+    commands::set_checked("view.panel", show);
+    app::redraw();
+}
+
+pub fn toggle_blame() {
+    let current = STATE.with(|s| {
+        let id = s.current_id()?;
+        let gutter = s.map.get(&id)?.gutter.clone()?;
+        Some((s.current_editor()?, gutter))
+    });
+    if let Some((mut ed, gutter)) = current {
+        let show = gutter.lock().unwrap().toggle_blame();
+        gutter::set_blame_visible(&mut ed, show);
     }
 }
 
+pub fn about() {
+    dialog::message_title("About");
+    dialog::message_default("A minimal text editor written using fltk-rs!")
+}
+
+pub fn tabs_cb(_t: &mut group::Tabs) {
+    fbr::follow_active();
+}
+
 pub fn tab_close_cb(g: &mut impl GroupExt) {
     if app::callback_reason() == CallbackReason::Closed {
         let ed = text::TextEditor::from_dyn_widget(&g.child(0).unwrap()).unwrap();
@@ -168,18 +259,24 @@ pub fn tab_close_cb(g: &mut impl GroupExt) {
         let buf = ed.buffer().unwrap();
         let mut parent = g.parent().unwrap();
         parent.remove(g);
+        let closed = STATE.with(move |s| s.map.remove(&edid));
+        // the gutter's pending updates must not touch the freed buffer
+        if let Some(gutter) = closed.as_ref().and_then(|b| b.gutter.as_ref()) {
+            gutter.lock().unwrap().close();
+        }
         unsafe {
             text::TextBuffer::delete(buf);
         }
-        STATE.with(move |s| s.map.remove(&edid));
+        if let Some(f) = closed.and_then(|b| b.current_file) {
+            lsp::close(&f);
+        }
         parent.set_damage(true);
     }
 }
 
-#[cfg(feature = "term")]
 pub fn tab_splitter_cb(f: &mut frame::Frame, ev: Event) -> bool {
     let mut parent = group::Flex::from_dyn_widget(&f.parent().unwrap()).unwrap();
-    let term = app::widget_from_id::<group::Group>("term_group").unwrap();
+    let term = app::widget_from_id::<group::Tabs>("panel").unwrap();
     match ev {
         Event::Push => true,
         Event::Drag => {
@@ -0,0 +1,514 @@
+use crate::{cbs, completion, conflict, diffview, fbr, lsp, navigation, problems, tasks};
+
+#[cfg(feature = "highlight")]
+use crate::outline;
+#[cfg(feature = "term")]
+use crate::terminal;
+use fltk::{enums::*, menu, prelude::*, utils::oncelock::Lazy};
+
+/// An action shared by the menus, their shortcuts and the command palette.
+pub struct Command {
+    pub id: &'static str,
+    /// Path in the menu bar, e.g. "&File/Save\t"
+    pub path: &'static str,
+    pub shortcut: Shortcut,
+    pub flag: menu::MenuFlag,
+    /// Whether the command also appears in the editor's popup menu
+    pub popup: bool,
+    pub run: fn(),
+}
+
+impl Command {
+    fn new(id: &'static str, path: &'static str, shortcut: Shortcut, run: fn()) -> Self {
+        Self {
+            id,
+            path,
+            shortcut,
+            flag: menu::MenuFlag::Normal,
+            popup: false,
+            run,
+        }
+    }
+
+    fn with_flag(mut self, flag: menu::MenuFlag) -> Self {
+        self.flag = flag;
+        self
+    }
+
+    fn in_popup(mut self) -> Self {
+        self.popup = true;
+        self
+    }
+
+    /// The last component of the menu path, e.g. "Save\t"
+    pub fn label(&self) -> &'static str {
+        self.path.rsplit('/').next().unwrap()
+    }
+
+    /// A human readable name, e.g. "File: Save"
+    pub fn title(&self) -> String {
+        self.path
+            .replace('&', "")
+            .replace('\t', "")
+            .replace('/', ": ")
+    }
+}
+
+pub static COMMANDS: Lazy<Vec<Command>> = Lazy::new(|| {
+    use menu::MenuFlag as F;
+    #[allow(unused_mut)]
+    let mut v = vec![
+        Command::new(
+            "file.new_file",
+            "&File/New File...\t",
+            Shortcut::Ctrl | 'n',
+            cbs::new_file,
+        ),
+        Command::new(
+            "file.new_dir",
+            "&File/New Dir...\t",
+            Shortcut::Ctrl | Shortcut::Shift | 'n',
+            cbs::new_dir,
+        ),
+        Command::new(
+            "file.quick_open",
+            "&File/Quick Open...\t",
+            Shortcut::Ctrl | 'p',
+            cbs::quick_open,
+        ),
+        Command::new(
+            "file.open",
+            "&File/Open...\t",
+            Shortcut::Ctrl | 'o',
+            cbs::open,
+        ),
+        Command::new("file.save", "&File/Save\t", Shortcut::Ctrl | 's', cbs::save),
+        Command::new(
+            "file.save_as",
+            "&File/Save as...\t",
+            Shortcut::Ctrl | Shortcut::Shift | 'w',
+            cbs::save_as,
+        ),
+        Command::new(
+            "file.save_all",
+            "&File/Save All\t",
+            Shortcut::None,
+            cbs::save_all,
+        )
+        .with_flag(F::MenuDivider),
+        Command::new(
+            "file.rename",
+            "&File/Browser Selection/Rename...\t",
+            Shortcut::None | Key::F2,
+            fbr::rename,
+        ),
+        Command::new(
+            "file.duplicate",
+            "&File/Browser Selection/Duplicate\t",
+            Shortcut::None,
+            fbr::duplicate,
+        ),
+        Command::new(
+            "file.delete",
+            "&File/Browser Selection/Delete\t",
+            Shortcut::None | Key::Delete,
+            fbr::delete,
+        )
+        .with_flag(F::MenuDivider),
+        Command::new(
+            "file.select_for_compare",
+            "&File/Browser Selection/Select for Compare\t",
+            Shortcut::None,
+            fbr::select_for_compare,
+        ),
+        Command::new(
+            "file.compare_with_selected",
+            "&File/Browser Selection/Compare with Selected\t",
+            Shortcut::None,
+            fbr::compare_with_selected,
+        )
+        .with_flag(F::MenuDivider),
+        Command::new(
+            "file.copy_path",
+            "&File/Browser Selection/Copy Path\t",
+            Shortcut::Ctrl | Shortcut::Alt | 'c',
+            fbr::copy_path,
+        ),
+        Command::new(
+            "file.copy_relative_path",
+            "&File/Browser Selection/Copy Relative Path\t",
+            Shortcut::Ctrl | Shortcut::Shift | Shortcut::Alt | 'c',
+            fbr::copy_relative_path,
+        )
+        .with_flag(F::MenuDivider),
+        Command::new("file.quit", "&File/Quit\t", Shortcut::Ctrl | 'q', cbs::quit),
+        Command::new("edit.undo", "&Edit/Undo\t", Shortcut::Ctrl | 'z', cbs::undo).in_popup(),
+        Command::new("edit.redo", "&Edit/Redo\t", Shortcut::Ctrl | 'y', cbs::redo)
+            .with_flag(F::MenuDivider)
+            .in_popup(),
+        Command::new("edit.cut", "&Edit/Cut\t", Shortcut::Ctrl | 'x', cbs::cut).in_popup(),
+        Command::new("edit.copy", "&Edit/Copy\t", Shortcut::Ctrl | 'c', cbs::copy).in_popup(),
+        Command::new(
+            "edit.paste",
+            "&Edit/Paste\t",
+            Shortcut::Ctrl | 'v',
+            cbs::paste,
+        )
+        .with_flag(F::MenuDivider)
+        .in_popup(),
+        Command::new("edit.find", "&Edit/Find\t", Shortcut::Ctrl | 'f', cbs::find).in_popup(),
+        Command::new(
+            "edit.replace",
+            "&Edit/Replace\t",
+            Shortcut::Ctrl | 'h',
+            cbs::replace,
+        )
+        .in_popup(),
+        Command::new(
+            "edit.goto_line",
+            "&Edit/Go to Line...\t",
+            Shortcut::Ctrl | 'g',
+            cbs::goto_line,
+        ),
+        Command::new(
+            "edit.complete",
+            "&Edit/Complete\t",
+            Shortcut::Ctrl | ' ',
+            completion::complete,
+        ),
+        Command::new(
+            "edit.goto_definition",
+            "&Edit/Go to Definition\t",
+            Shortcut::None | Key::F12,
+            lsp::goto_definition,
+        )
+        .in_popup(),
+        Command::new(
+            "edit.find_references",
+            "&Edit/Find References\t",
+            Shortcut::Shift | Key::F12,
+            lsp::find_references,
+        )
+        .in_popup(),
+        Command::new(
+            "edit.go_back",
+            "&Edit/Go Back\t",
+            Shortcut::Alt | Key::Left,
+            navigation::back,
+        ),
+        Command::new(
+            "edit.go_forward",
+            "&Edit/Go Forward\t",
+            Shortcut::Alt | Key::Right,
+            navigation::forward,
+        ),
+        Command::new(
+            "edit.hover",
+            "&Edit/Show Hover\t",
+            Shortcut::Ctrl | 'k',
+            lsp::hover,
+        )
+        .with_flag(F::MenuDivider)
+        .in_popup(),
+        Command::new(
+            "edit.next_conflict",
+            "&Edit/Conflicts/Next Conflict\t",
+            Shortcut::Alt | Key::PageDown,
+            conflict::next,
+        ),
+        Command::new(
+            "edit.previous_conflict",
+            "&Edit/Conflicts/Previous Conflict\t",
+            Shortcut::Alt | Key::PageUp,
+            conflict::previous,
+        )
+        .with_flag(F::MenuDivider),
+        Command::new(
+            "edit.accept_ours",
+            "&Edit/Conflicts/Accept Ours\t",
+            Shortcut::None,
+            conflict::accept_ours,
+        )
+        .in_popup(),
+        Command::new(
+            "edit.accept_theirs",
+            "&Edit/Conflicts/Accept Theirs\t",
+            Shortcut::None,
+            conflict::accept_theirs,
+        )
+        .in_popup(),
+        Command::new(
+            "edit.accept_both",
+            "&Edit/Conflicts/Accept Both\t",
+            Shortcut::None,
+            conflict::accept_both,
+        )
+        .in_popup(),
+        Command::new(
+            "view.command_palette",
+            "&View/Command Palette...\t",
+            Shortcut::Ctrl | Shortcut::Shift | 'p',
+            cbs::command_palette,
+        )
+        .with_flag(F::MenuDivider),
+        Command::new(
+            "view.blame",
+            "&View/Toggle blame\t",
+            Shortcut::Ctrl | Shortcut::Alt | 'b',
+            cbs::toggle_blame,
+        )
+        .with_flag(F::MenuDivider)
+        .in_popup(),
+        Command::new(
+            "view.compare_saved",
+            "&View/Compare/With Saved File\t",
+            Shortcut::None,
+            diffview::compare_with_saved,
+        ),
+        Command::new(
+            "view.compare_head",
+            "&View/Compare/With HEAD\t",
+            Shortcut::None,
+            diffview::compare_with_head,
+        ),
+        Command::new(
+            "view.file_browser",
+            "&View/File browser\t",
+            Shortcut::None,
+            cbs::toggle_fbr,
+        )
+        .with_flag(F::Toggle),
+        Command::new(
+            "view.hidden_files",
+            "&View/Hidden files\t",
+            Shortcut::None,
+            fbr::toggle_hidden,
+        )
+        .with_flag(F::Toggle),
+        Command::new(
+            "view.dim_excluded",
+            "&View/Excluded files (dimmed)\t",
+            Shortcut::None,
+            fbr::toggle_dim_excluded,
+        )
+        .with_flag(F::Toggle),
+        Command::new(
+            "view.follow_active_file",
+            "&View/Follow active file\t",
+            Shortcut::None,
+            fbr::toggle_follow,
+        )
+        .with_flag(F::Toggle),
+        Command::new(
+            "view.reveal_active_file",
+            "&View/Reveal in file browser\t",
+            Shortcut::Ctrl | Shortcut::Shift | 'e',
+            fbr::reveal_active,
+        )
+        .with_flag(F::MenuDivider)
+        .in_popup(),
+    ];
+    v.extend([
+        Command::new(
+            "view.panel",
+            "&View/Panel\t",
+            Shortcut::None,
+            cbs::toggle_panel,
+        )
+        .with_flag(F::Toggle),
+        Command::new(
+            "tasks.rerun",
+            "&Tasks/Rerun Last Task\t",
+            Shortcut::Ctrl | Shortcut::Shift | 'r',
+            tasks::rerun,
+        ),
+        Command::new(
+            "tasks.check",
+            "&Tasks/Check Problems\t",
+            Shortcut::Ctrl | Shortcut::Shift | 'b',
+            problems::check,
+        ),
+        Command::new(
+            "tasks.next_problem",
+            "&Tasks/Next Problem\t",
+            Shortcut::None | Key::F8,
+            problems::next,
+        ),
+        Command::new(
+            "tasks.previous_problem",
+            "&Tasks/Previous Problem\t",
+            Shortcut::Shift | Key::F8,
+            problems::previous,
+        )
+        .with_flag(F::MenuDivider),
+        Command::new(
+            "tasks.configure",
+            "&Tasks/Configure Tasks...\t",
+            Shortcut::None,
+            tasks::configure,
+        )
+        .with_flag(F::MenuDivider),
+    ]);
+    #[cfg(feature = "term")]
+    v.extend([
+        Command::new(
+            "edit.run_in_terminal",
+            "&Edit/Run Selection in Terminal\t",
+            Shortcut::Ctrl | Shortcut::Shift | Key::Enter,
+            terminal::send_selection,
+        )
+        .in_popup(),
+        Command::new(
+            "terminal.new",
+            "&Terminal/New Terminal\t",
+            Shortcut::Ctrl | Shortcut::Shift | '`',
+            terminal::new_terminal,
+        ),
+        Command::new(
+            "terminal.open_here",
+            "&Terminal/Open Terminal Here\t",
+            Shortcut::None,
+            fbr::open_terminal_here,
+        ),
+        Command::new(
+            "terminal.rename",
+            "&Terminal/Rename Terminal...\t",
+            Shortcut::None,
+            terminal::rename_terminal,
+        ),
+        Command::new(
+            "terminal.close",
+            "&Terminal/Close Terminal\t",
+            Shortcut::None,
+            terminal::close_terminal,
+        )
+        .with_flag(F::MenuDivider),
+        Command::new(
+            "terminal.follow_browser",
+            "&Terminal/Follow File Browser\t",
+            Shortcut::None,
+            terminal::toggle_follow_browser,
+        )
+        .with_flag(F::Toggle),
+    ]);
+    #[cfg(feature = "highlight")]
+    v.push(Command::new(
+        "view.outline",
+        "&View/Outline\t",
+        Shortcut::Ctrl | Shortcut::Shift | 'o',
+        outline::show,
+    ));
+    v.push(Command::new(
+        "help.about",
+        "&Help/About\t",
+        Shortcut::None,
+        cbs::about,
+    ));
+    v
+});
+
+pub fn find(id: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.id == id)
+}
+
+pub fn run(id: &str) {
+    if let Some(cmd) = find(id) {
+        (cmd.run)();
+    }
+}
+
+/// Adds the command `id` to `m` under `label`
+pub fn add_item(m: &mut impl MenuExt, id: &str, label: &str) -> Option<i32> {
+    let cmd = find(id)?;
+    let run = cmd.run;
+    Some(m.add(label, cmd.shortcut, cmd.flag, move |_| run()))
+}
+
+/// Syncs the check mark of a toggle command in the menu bar
+pub fn set_checked(id: &str, flag: bool) {
+    if let (Some(cmd), Some(m)) = (find(id), app_menu()) {
+        if let Some(mut item) = m.find_item(cmd.path) {
+            if flag {
+                item.set();
+            } else {
+                item.clear();
+            }
+        }
+    }
+}
+
+fn app_menu() -> Option<menu::SysMenuBar> {
+    fltk::app::widget_from_id("menu")
+}
+
+/// The palette hint for `sc`, empty if there's none or its key has no name
+pub fn shortcut_label(sc: Shortcut) -> String {
+    let key = sc.bits() & 0xffff;
+    let f1 = Key::F1.bits();
+    let name = match key {
+        0x20 => "Space".to_string(),
+        k if (0x21..0x7f).contains(&k) => (k as u8 as char).to_ascii_uppercase().to_string(),
+        // FLTK numbers the function keys up to F35
+        k if (f1..f1 + 35).contains(&k) => format!("F{}", k - f1 + 1),
+        k => {
+            let named = [
+                (Key::Left, "Left"),
+                (Key::Right, "Right"),
+                (Key::Up, "Up"),
+                (Key::Down, "Down"),
+                (Key::Enter, "Enter"),
+                (Key::KPEnter, "Enter"),
+                (Key::Escape, "Esc"),
+                (Key::Tab, "Tab"),
+                (Key::BackSpace, "Backspace"),
+                (Key::Delete, "Delete"),
+                (Key::Insert, "Insert"),
+                (Key::Home, "Home"),
+                (Key::End, "End"),
+                (Key::PageUp, "PageUp"),
+                (Key::PageDown, "PageDown"),
+            ];
+            match named.iter().find(|(key, _)| key.bits() == k) {
+                Some((_, name)) => name.to_string(),
+                None => return String::new(),
+            }
+        }
+    };
+    let mut s = String::new();
+    if sc.contains(Shortcut::Ctrl) {
+        s.push_str("Ctrl+");
+    }
+    if sc.contains(Shortcut::Meta) {
+        s.push_str("Meta+");
+    }
+    if sc.contains(Shortcut::Alt) {
+        s.push_str("Alt+");
+    }
+    if sc.contains(Shortcut::Shift) {
+        s.push_str("Shift+");
+    }
+    s.push_str(&name);
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortcut_label_names_keys() {
+        assert_eq!(shortcut_label(Shortcut::Ctrl | 'p'), "Ctrl+P");
+        assert_eq!(shortcut_label(Shortcut::Ctrl | ' '), "Ctrl+Space");
+        assert_eq!(shortcut_label(Shortcut::Shift | Key::F12), "Shift+F12");
+        assert_eq!(
+            shortcut_label(Shortcut::Alt | Key::PageDown),
+            "Alt+PageDown"
+        );
+        assert_eq!(shortcut_label(Shortcut::None), "");
+    }
+
+    #[test]
+    fn shortcut_label_skips_unnamed_keys() {
+        assert_eq!(shortcut_label(Shortcut::Ctrl | Key::from_i32(0xff7f)), "");
+    }
+}
@@ -0,0 +1,493 @@
+use crate::{lsp, state::STATE, utils};
+use fltk::{enums::*, prelude::*, *};
+use std::{collections::BTreeSet, ops::Range, sync::Mutex};
+
+#[cfg(feature = "highlight")]
+use crate::highlight;
+
+const MAX_SHOWN: usize = 50;
+const MIN_WORD_LEN: usize = 3;
+const LIST_WIDTH: i32 = 280;
+const DOC_WIDTH: i32 = 320;
+const ROWS: i32 = 10;
+
+/// Where a candidate comes from, in the order they're ranked on equal scores
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Source {
+    Lsp,
+    Keyword,
+    Word,
+}
+
+impl Source {
+    fn tag(&self) -> &'static str {
+        match self {
+            Source::Lsp => "lsp",
+            Source::Keyword => "keyword",
+            Source::Word => "word",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Source::Lsp => Color::from_hex(0x61afef),
+            Source::Keyword => Color::from_hex(0xc678dd),
+            Source::Word => Color::Foreground.darker(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Candidate {
+    label: String,
+    insert: String,
+    /// What `insert` replaces instead of the typed word, as of the cursor being at `at`
+    range: Option<Range<i32>>,
+    at: i32,
+    detail: String,
+    doc: String,
+    source: Source,
+}
+
+impl Candidate {
+    fn new(label: &str, source: Source) -> Self {
+        Self {
+            label: label.to_string(),
+            insert: label.to_string(),
+            range: None,
+            at: 0,
+            detail: String::new(),
+            doc: String::new(),
+            source,
+        }
+    }
+}
+
+/// The open popup
+struct Session {
+    ed: text::TextEditor,
+    /// Where the completed word starts
+    start: i32,
+    candidates: Vec<Candidate>,
+    /// The candidates matching the typed prefix, best first
+    shown: Vec<usize>,
+    win: window::MenuWindow,
+    list: browser::HoldBrowser,
+    doc: text::TextDisplay,
+}
+
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+impl Session {
+    fn filter(&mut self) {
+        let buf = self.ed.buffer().unwrap();
+        let prefix = buf
+            .text_range(self.start, self.ed.insert_position())
+            .unwrap_or_default();
+        let mut scored: Vec<_> = self
+            .candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| utils::fuzzy_score(&prefix, &c.label).map(|sc| (sc, i)))
+            .collect();
+        scored.sort_by(|a, b| {
+            let (ca, cb) = (&self.candidates[a.1], &self.candidates[b.1]);
+            b.0.cmp(&a.0)
+                .then(ca.source.cmp(&cb.source))
+                .then(ca.label.cmp(&cb.label))
+        });
+        self.shown = scored.into_iter().take(MAX_SHOWN).map(|e| e.1).collect();
+        self.list.clear();
+        for i in &self.shown {
+            let c = &self.candidates[*i];
+            self.list.add(&format!(
+                "@.{}\t@C{}@.{}",
+                c.label,
+                c.source.color().bits(),
+                c.source.tag()
+            ));
+        }
+        if !self.shown.is_empty() {
+            self.list.select(1);
+        }
+        self.update_doc();
+    }
+
+    /// Adds the server's candidates, which replace ours with the same label
+    fn merge(&mut self, items: Vec<Candidate>) {
+        let selected = self.selected().map(|c| c.label.clone());
+        self.candidates = merged(std::mem::take(&mut self.candidates), items);
+        self.filter();
+        // keep the selection where the user moved it
+        let row = self
+            .shown
+            .iter()
+            .position(|i| Some(&self.candidates[*i].label) == selected.as_ref());
+        if let Some(row) = row {
+            self.list.select(row as i32 + 1);
+            self.update_doc();
+        }
+    }
+
+    fn selected(&self) -> Option<&Candidate> {
+        let idx = self.list.value();
+        if idx < 1 {
+            return None;
+        }
+        self.shown
+            .get(idx as usize - 1)
+            .map(|i| &self.candidates[*i])
+    }
+
+    /// Shows the documentation of the selected candidate, hiding its pane if there's none
+    fn update_doc(&mut self) {
+        let text = match self.selected() {
+            Some(c) if c.doc.is_empty() => c.detail.clone(),
+            Some(c) if c.detail.is_empty() => c.doc.clone(),
+            Some(c) => format!("{}\n\n{}", c.detail, c.doc),
+            None => String::new(),
+        };
+        self.doc.buffer().unwrap().set_text(&text);
+        let w = if text.is_empty() {
+            LIST_WIDTH
+        } else {
+            LIST_WIDTH + DOC_WIDTH
+        };
+        if self.win.w() != w {
+            let (x, y, h) = (self.win.x(), self.win.y(), self.win.h());
+            self.win.resize(x, y, w, h);
+        }
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The start of the word ending at `pos`
+fn word_start(buf: &text::TextBuffer, pos: i32) -> i32 {
+    let before = buf.text_range(buf.line_start(pos), pos).unwrap_or_default();
+    let len: usize = before
+        .chars()
+        .rev()
+        .take_while(|&c| is_word(c))
+        .map(char::len_utf8)
+        .sum();
+    pos - len as i32
+}
+
+/// The words of `texts`, except for `prefix` itself and numbers
+fn words(texts: &[String], prefix: &str) -> BTreeSet<String> {
+    texts
+        .iter()
+        .flat_map(|t| t.split(|c: char| !is_word(c)))
+        .filter(|w| w.chars().count() >= MIN_WORD_LEN && *w != prefix)
+        .filter(|w| !w.starts_with(|c: char| c.is_ascii_digit()))
+        .map(String::from)
+        .collect()
+}
+
+/// `ours` and the server's `items`, which replace ours with the same label
+fn merged(ours: Vec<Candidate>, mut items: Vec<Candidate>) -> Vec<Candidate> {
+    let labels: BTreeSet<_> = items.iter().map(|c| c.label.clone()).collect();
+    items.extend(ours.into_iter().filter(|c| !labels.contains(&c.label)));
+    items
+}
+
+/// The bytes `c` replaces with the cursor at `cursor`, in a buffer of `len` bytes:
+/// the range the server gave, or the word from `start`
+fn replaced(c: &Candidate, start: i32, cursor: i32, len: i32) -> Range<i32> {
+    let (from, to) = match c.range.clone() {
+        // the text typed since the request shifted the end of the range
+        Some(r) if r.end >= c.at => (r.start, r.end + cursor - c.at),
+        Some(r) => (r.start, cursor),
+        None => (start, cursor),
+    };
+    let from = from.min(cursor);
+    from..to.clamp(from, len)
+}
+
+fn close_window(mut win: window::MenuWindow) {
+    app::set_grab(None::<window::MenuWindow>);
+    win.hide();
+    app::delete_widget(win);
+}
+
+fn close() {
+    let session = SESSION.lock().unwrap().take();
+    if let Some(s) = session {
+        close_window(s.win);
+    }
+}
+
+/// Replaces the typed prefix, or the range the server gave, with the selected candidate
+fn accept() {
+    let Some(s) = SESSION.lock().unwrap().take() else {
+        return;
+    };
+    if let Some(c) = s.selected() {
+        let mut ed = s.ed.clone();
+        let mut buf = ed.buffer().unwrap();
+        let r = replaced(c, s.start, ed.insert_position(), buf.length());
+        buf.replace(r.start, r.end, &c.insert);
+        ed.set_insert_position(r.start + c.insert.len() as i32);
+        ed.do_callback();
+    }
+    close_window(s.win);
+}
+
+/// Moves the selection by `delta` rows, wrapping around
+fn select(delta: i32) {
+    if let Some(s) = SESSION.lock().unwrap().as_mut() {
+        let count = s.list.size();
+        if count == 0 {
+            return;
+        }
+        let row = (s.list.value() - 1 + delta).rem_euclid(count) + 1;
+        s.list.select(row);
+        if !s.list.displayed(row) {
+            s.list.middle_line(row);
+        }
+        s.update_doc();
+    }
+}
+
+/// Refilters the candidates after an edit, closing the popup if the word was left
+fn refilter() {
+    let keep = match SESSION.lock().unwrap().as_mut() {
+        Some(s) if s.ed.insert_position() >= s.start => {
+            s.filter();
+            !s.shown.is_empty()
+        }
+        _ => false,
+    };
+    if !keep {
+        close();
+    }
+}
+
+/// Sends the current key to the editor, which has the focus
+fn forward(popup: &window::MenuWindow) -> bool {
+    app::set_grab(None::<window::MenuWindow>);
+    let handled = app::handle_main(Event::KeyDown).unwrap_or_default();
+    app::set_grab(Some(popup.clone()));
+    handled
+}
+
+fn handle(popup: &mut window::MenuWindow, ev: Event) -> bool {
+    match ev {
+        Event::KeyDown => match app::event_key() {
+            Key::Up => {
+                select(-1);
+                true
+            }
+            Key::Down => {
+                select(1);
+                true
+            }
+            Key::Tab | Key::Enter | Key::KPEnter => {
+                accept();
+                true
+            }
+            Key::Escape => {
+                close();
+                true
+            }
+            Key::BackSpace => {
+                forward(popup);
+                refilter();
+                true
+            }
+            _ if !app::event_text().is_empty()
+                && app::event_text().chars().all(is_word)
+                && !app::is_event_command()
+                && !app::is_event_alt() =>
+            {
+                forward(popup);
+                refilter();
+                true
+            }
+            _ => {
+                close();
+                app::handle_main(Event::KeyDown).unwrap_or_default()
+            }
+        },
+        Event::Push if !app::event_inside(0, 0, popup.w(), popup.h()) => {
+            close();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Opens the popup below the word starting at `start` if any candidate matches it
+fn show(ed: text::TextEditor, start: i32, candidates: Vec<Candidate>) {
+    close();
+    let Some(win) = ed.window() else {
+        return;
+    };
+    let (x, y) = ed.position_to_xy(start);
+    let line_h = ed.text_size() + 4;
+    let mut popup = window::MenuWindow::new(
+        win.x_root() + x,
+        win.y_root() + y + line_h,
+        LIST_WIDTH + DOC_WIDTH,
+        ROWS * 18 + 4,
+        None,
+    );
+    let mut row = group::Flex::default_fill().row();
+    let mut list = browser::HoldBrowser::default();
+    list.set_column_char('\t');
+    list.set_column_widths(&[LIST_WIDTH - 70, 60]);
+    list.set_color(Color::Background2);
+    list.set_selection_color(Color::Selection);
+    list.set_text_size(12);
+    row.fixed(&list, LIST_WIDTH);
+    let mut doc = text::TextDisplay::default();
+    doc.set_buffer(text::TextBuffer::default());
+    doc.set_text_font(Font::Courier);
+    doc.set_text_size(12);
+    doc.set_color(Color::Background);
+    doc.set_text_color(Color::Foreground);
+    doc.wrap_mode(text::WrapMode::AtBounds, 0);
+    row.end();
+    popup.end();
+    popup.set_border(false);
+    list.set_callback(|_| {
+        if app::event_clicks() {
+            accept();
+        } else if let Some(s) = SESSION.lock().unwrap().as_mut() {
+            s.update_doc();
+        }
+    });
+    popup.handle(handle);
+    let mut session = Session {
+        ed,
+        start,
+        candidates,
+        shown: vec![],
+        win: popup.clone(),
+        list,
+        doc,
+    };
+    session.filter();
+    if session.shown.is_empty() {
+        app::delete_widget(popup);
+        return;
+    }
+    popup.show();
+    app::set_grab(Some(popup));
+    *SESSION.lock().unwrap() = Some(session);
+}
+
+/// Offers completions for the word before the cursor from the open buffers,
+/// the language's keywords and its language server
+pub fn complete() {
+    close();
+    let Some((ed, path)) = STATE.with(|s| Some((s.editable_editor()?, s.current_file()))) else {
+        return;
+    };
+    let texts: Vec<_> = STATE.with(|s| s.map.values().map(|b| b.buf.text()).collect());
+    let buf = ed.buffer().unwrap();
+    let pos = ed.insert_position();
+    let start = word_start(&buf, pos);
+    let prefix = buf.text_range(start, pos).unwrap_or_default();
+    #[allow(unused_mut)]
+    let mut keywords = BTreeSet::new();
+    #[cfg(feature = "highlight")]
+    if let Some(p) = path.as_deref() {
+        keywords.extend(highlight::keywords(p));
+    }
+    let mut candidates: Vec<_> = keywords
+        .iter()
+        .map(|k| Candidate::new(k, Source::Keyword))
+        .collect();
+    candidates.extend(
+        words(&texts, &prefix)
+            .difference(&keywords)
+            .map(|w| Candidate::new(w, Source::Word)),
+    );
+    show(ed.clone(), start, candidates.clone());
+    let opened = SESSION.lock().unwrap().is_some();
+    // the server's candidates join ours when they arrive, taking precedence
+    if let Some(p) = path.as_deref() {
+        lsp::completions(p, pos, move |items| {
+            let items: Vec<_> = items
+                .into_iter()
+                .map(|c| Candidate {
+                    label: c.label,
+                    insert: c.insert,
+                    range: c.range,
+                    at: pos,
+                    detail: c.detail,
+                    doc: c.documentation,
+                    source: Source::Lsp,
+                })
+                .collect();
+            if let Some(s) = SESSION.lock().unwrap().as_mut() {
+                if s.ed.as_widget_ptr() == ed.as_widget_ptr() && s.start == start {
+                    s.merge(items);
+                    return;
+                }
+            }
+            // none of ours matched, unless the popup was dismissed or the cursor moved
+            if !opened && ed.insert_position() == pos {
+                show(ed, start, merged(candidates, items));
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(label: &str, range: Option<Range<i32>>, at: i32) -> Candidate {
+        Candidate {
+            insert: format!("{}()", label),
+            range,
+            at,
+            ..Candidate::new(label, Source::Lsp)
+        }
+    }
+
+    #[test]
+    fn collects_words() {
+        let texts = [
+            "fn foo_bar(x: u32) -> Bar { foo_bar(1) }".to_string(),
+            "let héllo = 123abc + ab;".to_string(),
+        ];
+        let found: Vec<_> = words(&texts, "foo_bar").into_iter().collect();
+        assert_eq!(found, ["Bar", "héllo", "let", "u32"]);
+    }
+
+    #[test]
+    fn server_items_replace_ours() {
+        let ours = vec![
+            Candidate::new("fn", Source::Keyword),
+            Candidate::new("foo", Source::Word),
+            Candidate::new("bar", Source::Word),
+        ];
+        let all = merged(ours, vec![server("foo", None, 0)]);
+        let mut labels: Vec<_> = all.iter().map(|c| c.label.as_str()).collect();
+        labels.sort();
+        assert_eq!(labels, ["bar", "fn", "foo"]);
+        let foo = all.iter().find(|c| c.label == "foo").unwrap();
+        assert_eq!((foo.source, foo.insert.as_str()), (Source::Lsp, "foo()"));
+    }
+
+    #[test]
+    fn shifts_ranges_by_what_was_typed() {
+        // the typed word from `start`
+        assert_eq!(replaced(&Candidate::new("a", Source::Word), 4, 7, 20), 4..7);
+        // requested at 5, two bytes typed since
+        assert_eq!(replaced(&server("a", Some(2..5), 5), 4, 7, 20), 2..7);
+        // the range goes past the cursor, up to the end of the buffer
+        assert_eq!(replaced(&server("a", Some(2..8), 5), 4, 6, 20), 2..9);
+        assert_eq!(replaced(&server("a", Some(2..8), 5), 4, 6, 8), 2..8);
+        // a range ending before the request's position stops at the cursor
+        assert_eq!(replaced(&server("a", Some(2..4), 5), 4, 7, 20), 2..7);
+        // deleted back over the start of the range
+        assert_eq!(replaced(&server("a", Some(4..6), 6), 4, 3, 20), 3..3);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+/// A language server and the files it handles
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LanguageServer {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// File extensions without the dot, e.g. "rs"
+    pub extensions: Vec<String>,
+}
+
+/// User settings, stored in config.toml under the user's config dir
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Show dotfiles in the file browser
+    pub show_hidden: bool,
+    /// Show gitignored and excluded entries dimmed instead of hiding them
+    pub dim_excluded: bool,
+    /// Globs hidden from the file browser, relative to the project root
+    pub exclude: Vec<String>,
+    /// Reveal the active tab's file in the file browser when switching tabs
+    pub follow_active_file: bool,
+    /// `cd` the terminal into the directories clicked in the file browser
+    pub terminal_follows_browser: bool,
+    /// Servers by language id, e.g. "rust"
+    pub language_servers: BTreeMap<String, LanguageServer>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            show_hidden: false,
+            dim_excluded: false,
+            exclude: vec![".git".to_string()],
+            follow_active_file: false,
+            terminal_follows_browser: false,
+            language_servers: BTreeMap::from([(
+                "rust".to_string(),
+                LanguageServer {
+                    command: "rust-analyzer".to_string(),
+                    args: vec![],
+                    extensions: vec!["rs".to_string()],
+                },
+            )]),
+        }
+    }
+}
+
+fn config_file() -> Option<PathBuf> {
+    utils::config_dir().map(|d| d.join("config.toml"))
+}
+
+impl Config {
+    pub fn load() -> Self {
+        config_file()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|s| match toml::from_str(&s) {
+                Ok(c) => Some(c),
+                Err(e) => {
+                    eprintln!("Invalid config: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        if let (Some(p), Ok(s)) = (config_file(), toml::to_string_pretty(self)) {
+            if let Some(parent) = p.parent() {
+                fs::create_dir_all(parent).ok();
+            }
+            fs::write(p, s).ok();
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use crate::state::STATE;
+use fltk::{enums::*, prelude::*, *};
+use std::ops::Range;
+
+// styles of a file with conflicts
+const NORMAL: u8 = b'A';
+const MARKER: u8 = b'B';
+const OURS: u8 = b'C';
+const BASE: u8 = b'D';
+const THEIRS: u8 = b'E';
+
+/// A `<<<<<<<`/`=======`/`>>>>>>>` block, as byte ranges of the buffer
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    /// From the start of the `<<<<<<<` line to the end of the `>>>>>>>` line
+    pub range: Range<usize>,
+    pub ours: Range<usize>,
+    /// The `|||||||` section of diff3 style conflicts
+    pub base: Option<Range<usize>>,
+    pub theirs: Range<usize>,
+}
+
+#[derive(Clone, Copy)]
+enum Resolution {
+    Ours,
+    Theirs,
+    Both,
+}
+
+enum Section {
+    Ours,
+    Base,
+    Theirs,
+}
+
+pub fn parse(text: &str) -> Vec<Conflict> {
+    let mut conflicts = vec![];
+    let mut current: Option<(Conflict, Section)> = None;
+    let mut pos = 0;
+    for line in text.split_inclusive('\n') {
+        let next = pos + line.len();
+        if line.starts_with("<<<<<<<") {
+            let c = Conflict {
+                range: pos..next,
+                ours: next..next,
+                base: None,
+                theirs: next..next,
+            };
+            current = Some((c, Section::Ours));
+        } else if let Some((c, section)) = current.as_mut() {
+            match section {
+                Section::Ours if line.starts_with("|||||||") => {
+                    c.ours.end = pos;
+                    c.base = Some(next..next);
+                    *section = Section::Base;
+                }
+                Section::Ours | Section::Base if line.starts_with("=======") => {
+                    match c.base.as_mut() {
+                        Some(base) => base.end = pos,
+                        None => c.ours.end = pos,
+                    }
+                    c.theirs = next..next;
+                    *section = Section::Theirs;
+                }
+                Section::Theirs if line.starts_with(">>>>>>>") => {
+                    c.theirs.end = pos;
+                    c.range.end = next;
+                    conflicts.push(current.take().unwrap().0);
+                }
+                _ => (),
+            }
+        }
+        pos = next;
+    }
+    conflicts
+}
+
+fn styles() -> Vec<text::StyleTableEntryExt> {
+    let entry = |color: Color, bgcolor: u32| text::StyleTableEntryExt {
+        color,
+        font: Font::Courier,
+        size: app::font_size(),
+        attr: if bgcolor == 0 {
+            text::TextAttr::None
+        } else {
+            text::TextAttr::BgColorExt
+        },
+        bgcolor: Color::from_hex(bgcolor),
+    };
+    vec![
+        entry(Color::Foreground, 0),
+        entry(Color::Foreground.darker(), 0x3a3f4b),
+        entry(Color::Foreground, 0x2f4431),
+        entry(Color::Foreground, 0x3b3345),
+        entry(Color::Foreground, 0x263c52),
+    ]
+}
+
+fn style_text(text: &str, conflicts: &[Conflict]) -> String {
+    let mut style = vec![NORMAL; text.len()];
+    for c in conflicts {
+        style[c.range.clone()].fill(MARKER);
+        style[c.ours.clone()].fill(OURS);
+        if let Some(base) = c.base.clone() {
+            style[base].fill(BASE);
+        }
+        style[c.theirs.clone()].fill(THEIRS);
+    }
+    String::from_utf8(style).unwrap()
+}
+
+/// Colors the conflicts of `buf` in `ed` in place of its syntax highlighting,
+/// calling `resolved` once they're all gone to bring it back
+pub fn highlight(
+    ed: &mut text::TextEditor,
+    buf: &mut text::TextBuffer,
+    resolved: impl FnMut() + 'static,
+) {
+    let mut sbuf = text::TextBuffer::default();
+    let text = buf.text();
+    sbuf.set_text(&style_text(&text, &parse(&text)));
+    ed.set_highlight_data_ext(sbuf.clone(), styles());
+    buf.add_modify_callback({
+        let buf = buf.clone();
+        let mut resolved = Some(resolved);
+        move |_, _, _, _, _| {
+            if resolved.is_none() {
+                return;
+            }
+            let text = buf.text();
+            let conflicts = parse(&text);
+            if conflicts.is_empty() {
+                let mut resolved = resolved.take().unwrap();
+                // once the edit is through
+                app::add_timeout3(0.0, move |_| resolved());
+            } else {
+                sbuf.set_text(&style_text(&text, &conflicts));
+            }
+        }
+    });
+}
+
+fn current() -> Option<(text::TextEditor, text::TextBuffer)> {
+    STATE.with(|s| Some((s.current_editor()?, s.buf()?)))
+}
+
+/// Like `current`, unless the tab is read-only
+fn editable() -> Option<(text::TextEditor, text::TextBuffer)> {
+    STATE.with(|s| Some((s.editable_editor()?, s.buf()?)))
+}
+
+/// Moves the cursor to the next or previous conflict, wrapping around
+fn goto(forward: bool) {
+    if let Some((mut ed, buf)) = current() {
+        let conflicts = parse(&buf.text());
+        let pos = ed.insert_position() as usize;
+        let target = if forward {
+            conflicts
+                .iter()
+                .find(|c| c.range.start > pos)
+                .or(conflicts.first())
+        } else {
+            conflicts
+                .iter()
+                .rev()
+                .find(|c| c.range.start < pos)
+                .or(conflicts.last())
+        };
+        if let Some(c) = target {
+            ed.set_insert_position(c.range.start as i32);
+            ed.show_insert_position();
+            ed.take_focus().ok();
+        }
+    }
+}
+
+pub fn next() {
+    goto(true);
+}
+
+pub fn previous() {
+    goto(false);
+}
+
+/// Resolves the conflict under the cursor
+fn accept(resolution: Resolution) {
+    if let Some((mut ed, mut buf)) = editable() {
+        let text = buf.text();
+        let pos = ed.insert_position() as usize;
+        let Some(c) = parse(&text).into_iter().find(|c| c.range.contains(&pos)) else {
+            return;
+        };
+        let (ours, theirs) = (&text[c.ours.clone()], &text[c.theirs.clone()]);
+        let resolved = match resolution {
+            Resolution::Ours => ours.to_string(),
+            Resolution::Theirs => theirs.to_string(),
+            Resolution::Both => format!("{}{}", ours, theirs),
+        };
+        buf.replace(c.range.start as i32, c.range.end as i32, &resolved);
+        ed.set_insert_position(c.range.start as i32);
+        ed.do_callback();
+    }
+}
+
+pub fn accept_ours() {
+    accept(Resolution::Ours);
+}
+
+pub fn accept_theirs() {
+    accept(Resolution::Theirs);
+}
+
+pub fn accept_both() {
+    accept(Resolution::Both);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_conflict() {
+        let text = "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> branch\nb\n";
+        let c = parse(text);
+        assert_eq!(c.len(), 1);
+        assert_eq!(&text[c[0].range.clone()], &text[2..text.len() - 2]);
+        assert_eq!(&text[c[0].ours.clone()], "ours\n");
+        assert!(c[0].base.is_none());
+        assert_eq!(&text[c[0].theirs.clone()], "theirs\n");
+    }
+
+    #[test]
+    fn parses_diff3_base() {
+        let text = "<<<<<<< HEAD\nours\n||||||| base\nbase\n=======\ntheirs\n>>>>>>> branch";
+        let c = parse(text);
+        assert_eq!(c.len(), 1);
+        assert_eq!(&text[c[0].ours.clone()], "ours\n");
+        assert_eq!(&text[c[0].base.clone().unwrap()], "base\n");
+        assert_eq!(&text[c[0].theirs.clone()], "theirs\n");
+        assert_eq!(c[0].range, 0..text.len());
+    }
+
+    #[test]
+    fn parses_several_and_empty_sides() {
+        let text = "<<<<<<< a\n=======\nx\n>>>>>>> b\nmid\n<<<<<<< a\ny\n=======\n>>>>>>> b\n";
+        let c = parse(text);
+        assert_eq!(c.len(), 2);
+        assert!(c[0].ours.is_empty());
+        assert_eq!(&text[c[0].theirs.clone()], "x\n");
+        assert_eq!(&text[c[1].ours.clone()], "y\n");
+        assert!(c[1].theirs.is_empty());
+    }
+
+    #[test]
+    fn ignores_unterminated() {
+        assert!(parse("plain\n").is_empty());
+        assert!(parse("<<<<<<< a\nours\n=======\ntheirs\n").is_empty());
+        assert!(parse("<<<<<<< a\nours\n>>>>>>> b\n").is_empty());
+    }
+
+    #[test]
+    fn styles_sections() {
+        let text = "a\n<<<<<<< x\no\n||||||| y\nb\n=======\nt\n>>>>>>> z\n";
+        let style = style_text(text, &parse(text));
+        assert_eq!(style.len(), text.len());
+        let expected = [
+            "A".repeat(2),
+            "B".repeat(10),
+            "C".repeat(2),
+            "B".repeat(10),
+            "D".repeat(2),
+            "B".repeat(8),
+            "E".repeat(2),
+            "B".repeat(10),
+        ]
+        .concat();
+        assert_eq!(style, expected);
+    }
+}
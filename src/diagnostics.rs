@@ -0,0 +1,299 @@
+use crate::{problems, state::STATE};
+use fltk::enums::Color;
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    fn parse(s: &str) -> Self {
+        match s {
+            "error" | "fatal error" | "error: internal compiler error" => Severity::Error,
+            "warning" => Severity::Warning,
+            _ => Severity::Info,
+        }
+    }
+
+    pub fn icon(&self) -> &'static str {
+        match self {
+            Severity::Error => "\u{2716}",
+            Severity::Warning => "\u{26a0}",
+            Severity::Info => "\u{2139}",
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Severity::Error => Color::from_hex(0xe06c75),
+            Severity::Warning => Color::from_hex(0xe5c07b),
+            Severity::Info => Color::from_hex(0x61afef),
+        }
+    }
+}
+
+/// A message about a range of a file, with 1-based lines and columns
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    pub line: usize,
+    pub col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// The diagnostics of every source, e.g. the last build
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    sources: HashMap<String, Vec<Diagnostic>>,
+}
+
+impl Diagnostics {
+    /// Every diagnostic, errors first
+    pub fn all(&self) -> Vec<Diagnostic> {
+        let mut v: Vec<_> = self.sources.values().flatten().cloned().collect();
+        v.sort_by(|a, b| {
+            (a.severity, &a.path, a.line, a.col).cmp(&(b.severity, &b.path, b.line, b.col))
+        });
+        v.dedup();
+        v
+    }
+
+    /// The diagnostics of `path`, one per span, the most severe one with every message
+    pub fn for_file(&self, path: &Path) -> Vec<Diagnostic> {
+        let mut v: Vec<_> = self
+            .sources
+            .values()
+            .flatten()
+            .filter(|d| d.path == path)
+            .cloned()
+            .collect();
+        v.sort_by(|a, b| {
+            (a.line, a.col, a.end_line, a.end_col, a.severity)
+                .cmp(&(b.line, b.col, b.end_line, b.end_col, b.severity))
+        });
+        v.dedup_by(|d, kept| {
+            let same = (d.line, d.col, d.end_line, d.end_col)
+                == (kept.line, kept.col, kept.end_line, kept.end_col);
+            if same && !kept.message.lines().any(|l| l == d.message) {
+                kept.message = format!("{}\n{}", kept.message, d.message);
+            }
+            same
+        });
+        v
+    }
+}
+
+/// Replaces the diagnostics of `source` and updates their views
+pub fn publish(source: &str, diagnostics: Vec<Diagnostic>) {
+    let source = source.to_string();
+    STATE.with(move |s| {
+        s.diagnostics
+            .sources
+            .insert(source.clone(), diagnostics.clone());
+    });
+    STATE.with(|s| s.update_diagnostics());
+    problems::refresh();
+}
+
+fn resolve(dir: &Path, file: &str) -> PathBuf {
+    let p = dir.join(file);
+    p.canonicalize().unwrap_or(p)
+}
+
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<CompilerMessage>,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessage {
+    message: String,
+    level: String,
+    spans: Vec<Span>,
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    file_name: String,
+    line_start: usize,
+    line_end: usize,
+    column_start: usize,
+    column_end: usize,
+    is_primary: bool,
+}
+
+/// Parses a line of `cargo --message-format=json` output run in `dir`,
+/// returning the diagnostic and its human readable rendering
+pub fn parse_cargo_json(dir: &Path, line: &str) -> Option<(Option<Diagnostic>, String)> {
+    let msg: CargoMessage = serde_json::from_str(line).ok()?;
+    if msg.reason != "compiler-message" {
+        return None;
+    }
+    let msg = msg.message?;
+    let rendered = msg.rendered.clone().unwrap_or_else(|| msg.message.clone());
+    let diagnostic = msg
+        .spans
+        .iter()
+        .find(|s| s.is_primary)
+        .map(|span| Diagnostic {
+            path: resolve(dir, &span.file_name),
+            line: span.line_start,
+            col: span.column_start,
+            end_line: span.line_end,
+            end_col: span.column_end,
+            severity: Severity::parse(&msg.level),
+            message: msg.message.clone(),
+        });
+    Some((diagnostic, rendered))
+}
+
+/// Collects diagnostics from human readable compiler output,
+/// e.g. gcc's `file:line:col: error: msg` or rustc's `error: msg` followed by `--> file:line:col`
+pub struct TextParser {
+    dir: PathBuf,
+    location: Regex,
+    header: Regex,
+    // the last `error: msg` line, waiting for its location
+    pending: Option<(Severity, String)>,
+}
+
+impl TextParser {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            location: Regex::new(
+                r"^\s*(?:-->\s*)?([^\s:][^:]*|[A-Za-z]:[^:]+):(\d+):(\d+):?\s*(?:(fatal error|error|warning|note|info)[^:]*:\s*)?(.*)$",
+            )
+            .unwrap(),
+            header: Regex::new(r"^(error|warning)(?:\[\w+\])?:\s*(.*)$").unwrap(),
+            pending: None,
+        }
+    }
+
+    pub fn parse_line(&mut self, line: &str) -> Option<Diagnostic> {
+        if let Some(c) = self.header.captures(line) {
+            self.pending = Some((Severity::parse(&c[1]), c[2].to_string()));
+            return None;
+        }
+        let c = self.location.captures(line)?;
+        let (line, col) = (c[2].parse().ok()?, c[3].parse().ok()?);
+        let (severity, message) = match (c.get(4), self.pending.take()) {
+            (Some(level), _) => (Severity::parse(level.as_str()), c[5].to_string()),
+            (None, Some(pending)) => pending,
+            (None, None) => return None,
+        };
+        Some(Diagnostic {
+            path: resolve(&self.dir, &c[1]),
+            line,
+            col,
+            end_line: line,
+            end_col: col,
+            severity,
+            message,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIR: &str = "/nonexistent/project";
+
+    #[test]
+    fn for_file_dedups_spans() {
+        let d = |severity, message: &str| Diagnostic {
+            path: PathBuf::from("a.rs"),
+            line: 1,
+            col: 2,
+            end_line: 1,
+            end_col: 4,
+            severity,
+            message: message.to_string(),
+        };
+        let mut other = d(Severity::Error, "b");
+        other.path = PathBuf::from("b.rs");
+        let mut sources = HashMap::new();
+        sources.insert("build".to_string(), vec![d(Severity::Warning, "a"), other]);
+        sources.insert(
+            "lsp".to_string(),
+            vec![d(Severity::Warning, "a"), d(Severity::Error, "b")],
+        );
+        let v = Diagnostics { sources }.for_file(Path::new("a.rs"));
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].severity, Severity::Error);
+        assert_eq!(v[0].message, "b\na");
+    }
+
+    #[test]
+    fn parses_cargo_json() {
+        let line = r#"{"reason":"compiler-message","message":{"message":"unused variable: `x`","level":"warning","rendered":"warning: unused variable: `x`\n","spans":[{"file_name":"src/other.rs","line_start":1,"line_end":1,"column_start":1,"column_end":2,"is_primary":false},{"file_name":"src/main.rs","line_start":3,"line_end":4,"column_start":9,"column_end":10,"is_primary":true}]}}"#;
+        let (d, rendered) = parse_cargo_json(Path::new(DIR), line).unwrap();
+        assert_eq!(rendered, "warning: unused variable: `x`\n");
+        let d = d.unwrap();
+        assert_eq!(d.path, Path::new(DIR).join("src/main.rs"));
+        assert_eq!((d.line, d.col, d.end_line, d.end_col), (3, 9, 4, 10));
+        assert_eq!(d.severity, Severity::Warning);
+        assert_eq!(d.message, "unused variable: `x`");
+    }
+
+    #[test]
+    fn cargo_json_without_primary_span() {
+        let line = r#"{"reason":"compiler-message","message":{"message":"aborting due to 2 previous errors","level":"error","rendered":null,"spans":[]}}"#;
+        let (d, rendered) = parse_cargo_json(Path::new(DIR), line).unwrap();
+        assert!(d.is_none());
+        assert_eq!(rendered, "aborting due to 2 previous errors");
+    }
+
+    #[test]
+    fn skips_other_cargo_lines() {
+        let dir = Path::new(DIR);
+        assert!(parse_cargo_json(dir, r#"{"reason":"build-finished","success":true}"#).is_none());
+        assert!(parse_cargo_json(dir, "   Compiling red v0.1.0").is_none());
+    }
+
+    #[test]
+    fn parses_gcc_output() {
+        let mut p = TextParser::new(Path::new(DIR));
+        let d = p
+            .parse_line("main.c:12:5: error: expected ';' before '}' token")
+            .unwrap();
+        assert_eq!(d.path, Path::new(DIR).join("main.c"));
+        assert_eq!((d.line, d.col), (12, 5));
+        assert_eq!(d.severity, Severity::Error);
+        assert_eq!(d.message, "expected ';' before '}' token");
+        let d = p
+            .parse_line("util.h:3:1: warning: unused function")
+            .unwrap();
+        assert_eq!(d.severity, Severity::Warning);
+        assert!(p.parse_line("make: *** [all] Error 1").is_none());
+    }
+
+    #[test]
+    fn parses_rustc_output() {
+        let mut p = TextParser::new(Path::new(DIR));
+        assert!(p
+            .parse_line("error[E0425]: cannot find value `y` in this scope")
+            .is_none());
+        let d = p.parse_line("  --> src/main.rs:7:13").unwrap();
+        assert_eq!(d.path, Path::new(DIR).join("src/main.rs"));
+        assert_eq!((d.line, d.col), (7, 13));
+        assert_eq!(d.severity, Severity::Error);
+        assert_eq!(d.message, "cannot find value `y` in this scope");
+        // a location is only reported once
+        assert!(p.parse_line("  --> src/main.rs:7:13").is_none());
+    }
+}
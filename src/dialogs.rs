@@ -1,9 +1,78 @@
 #![allow(dead_code)]
 
-use crate::state::STATE;
+use crate::{commands, history::HistoryKind, navigation, state::STATE, utils};
 use fltk::{prelude::*, *};
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub fn menu_escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' | '/' | '_' => out.push('\\'),
+            '&' => out.push('&'),
+            _ => (),
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Adds Up/Down navigation to `inp` and a dropdown listing the entries of `kind`.
+fn add_history(inp: &mut input::Input, kind: HistoryKind) -> menu::MenuButton {
+    let mut m = menu::MenuButton::default().with_label("@2>");
+    m.set_tooltip("History");
+    inp.handle(move |i, ev| {
+        if ev == enums::Event::KeyDown {
+            let key = app::event_key();
+            if key == enums::Key::Up || key == enums::Key::Down {
+                let val = STATE.with(move |s| {
+                    let h = s.history(kind);
+                    if key == enums::Key::Up {
+                        h.older()
+                    } else {
+                        h.newer()
+                    }
+                });
+                if let Some(val) = val {
+                    i.set_value(&val);
+                    i.set_position(val.len() as i32).ok();
+                }
+                return true;
+            }
+        }
+        false
+    });
+    m.handle(move |m, ev| {
+        if ev == enums::Event::Push {
+            m.clear();
+            let entries = STATE.with(move |s| s.history(kind).entries().to_vec());
+            for e in entries {
+                m.add_choice(&menu_escape(&e));
+            }
+        }
+        false
+    });
+    m.set_callback({
+        let mut inp = inp.clone();
+        move |m| {
+            let idx = m.value();
+            if idx < 0 {
+                return;
+            }
+            let val = STATE.with(move |s| s.history(kind).entries().get(idx as usize).cloned());
+            if let Some(val) = val {
+                inp.set_value(&val);
+                inp.take_focus().ok();
+            }
+        }
+    });
+    m
+}
 
 pub struct FindDialog {
     win: window::Window,
@@ -20,6 +89,8 @@ impl FindDialog {
         row.fixed(&f, 30);
         let mut i = input::Input::default();
         i.set_trigger(enums::CallbackTrigger::EnterKeyAlways);
+        let hist = add_history(&mut i, HistoryKind::Search);
+        row.fixed(&hist, 20);
         let mut reg = button::ToggleButton::default().with_label(".*");
         reg.set_selection_color(reg.color().lighter());
         reg.set_tooltip("Use regex");
@@ -39,6 +110,7 @@ impl FindDialog {
                     STATE.with({
                         let idx = idx.clone();
                         move |s| {
+                            s.search_history.push(&val);
                             if let Some(buf) = s.buf().as_mut() {
                                 let text = buf.text();
                                 if reg_val {
@@ -100,13 +172,312 @@ impl FindDialog {
     }
 }
 
+/// Parses `line`, `line:col`, `:col`, `+N`/`-N` (relative to `current`) and `N%`
+/// into a 1-based (line, col), clamping the line to `1..=total`.
+fn parse_goto(s: &str, current: i32, total: i32) -> Option<(i32, i32)> {
+    let s = s.trim();
+    let (l, c) = match s.split_once(':') {
+        Some((l, c)) => (l.trim(), c.trim().parse::<i32>().ok()?),
+        None => (s, 1),
+    };
+    let line = if let Some(p) = l.strip_suffix('%') {
+        let p: f64 = p.trim().parse().ok().filter(|p: &f64| p.is_finite())?;
+        (total as f64 * p.clamp(0.0, 100.0) / 100.0).round() as i32
+    } else if let Some(n) = l.strip_prefix('+') {
+        current.saturating_add(n.trim().parse::<i32>().ok()?)
+    } else if let Some(n) = l.strip_prefix('-') {
+        current.saturating_sub(n.trim().parse::<i32>().ok()?)
+    } else if l.is_empty() {
+        current
+    } else {
+        l.parse().ok()?
+    };
+    Some((line.clamp(1, total.max(1)), c.max(1)))
+}
+
+/// Moves the cursor of `ed` to the 1-based `line` and `col`, and scrolls it into view
+pub fn goto_position(ed: &mut text::TextEditor, line: i32, col: i32) {
+    let mut buf = ed.buffer().unwrap();
+    let start = buf.skip_lines(0, line - 1);
+    let end = buf.line_end(start);
+    let text = buf.text_range(start, end).unwrap_or_default();
+    let offset = text
+        .char_indices()
+        .nth(col as usize - 1)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+    ed.set_insert_position(start + offset as i32);
+    ed.show_insert_position();
+}
+
+/// Opens `path` with the cursor at the 1-based `line` and `col`,
+/// remembering where the cursor was for going back
+pub fn open_at(path: &Path, line: i32, col: i32) {
+    if exists(path) {
+        navigation::record();
+        goto_file(path, line, col);
+    }
+}
+
+/// Whether `path` is still there, telling the user if it's not
+fn exists(path: &Path) -> bool {
+    let found = path.exists();
+    if !found {
+        dialog::alert_default(&format!("{} doesn't exist anymore", path.display()));
+    }
+    found
+}
+
+/// Opens `path` with the cursor at the 1-based `line` and `col`
+pub fn show_position(path: &Path, line: i32, col: i32) {
+    if exists(path) {
+        goto_file(path, line, col);
+    }
+}
+
+fn goto_file(path: &Path, line: i32, col: i32) {
+    let path = path.to_path_buf();
+    STATE.with(move |s| s.append(Some(path.clone())));
+    if let Some(mut ed) = STATE.with(|s| s.current_editor()) {
+        goto_position(&mut ed, line, col);
+        ed.take_focus().ok();
+    }
+}
+
+/// Shows `text` in a borderless popup below byte `pos` of `ed`,
+/// closed by the next click outside of it or key press, which still reaches the editor
+pub fn show_popup(ed: &text::TextEditor, pos: i32, text: &str) {
+    const MAX_LINES: usize = 20;
+    let Some(win) = ed.window() else {
+        return;
+    };
+    let (x, y) = ed.position_to_xy(pos);
+    draw::set_font(enums::Font::Courier, 12);
+    let w = text
+        .lines()
+        .map(|l| draw::width(l) as i32)
+        .max()
+        .unwrap_or_default()
+        .clamp(100, 600)
+        + 20;
+    let h = text.lines().count().clamp(1, MAX_LINES) as i32 * (draw::height() + 2) + 10;
+    let line_h = ed.text_size() + 4;
+    let mut popup =
+        window::MenuWindow::new(win.x_root() + x, win.y_root() + y + line_h, w, h, None);
+    let mut disp = text::TextDisplay::new(0, 0, w, h, None);
+    let mut buf = text::TextBuffer::default();
+    buf.set_text(text);
+    disp.set_buffer(buf);
+    disp.set_text_font(enums::Font::Courier);
+    disp.set_text_size(12);
+    disp.set_color(enums::Color::Background2);
+    disp.set_text_color(enums::Color::Foreground);
+    disp.wrap_mode(text::WrapMode::AtBounds, 0);
+    popup.end();
+    popup.set_border(false);
+    popup.show();
+    app::set_grab(Some(popup.clone()));
+    popup.handle(|p, ev| match ev {
+        enums::Event::Push if app::event_inside(0, 0, p.w(), p.h()) => false,
+        enums::Event::Push | enums::Event::KeyDown => {
+            app::set_grab(None::<window::MenuWindow>);
+            p.hide();
+            app::delete_widget(p.clone());
+            // let the key through to the editor
+            ev == enums::Event::KeyDown && app::handle_main(ev).unwrap_or_default()
+        }
+        _ => false,
+    });
+}
+
+pub struct GotoDialog {
+    win: window::Window,
+}
+
+impl GotoDialog {
+    pub fn new() -> Self {
+        let mut win = window::Window::new(0, 0, 300, 50, "Go to Line").with_id("goto_line");
+        win.set_border(false);
+        let mut row = group::Flex::default_fill();
+        row.set_margin(10);
+        let f = frame::Frame::default().with_label("Line:");
+        row.fixed(&f, 30);
+        let mut i = input::Input::default();
+        i.set_trigger(enums::CallbackTrigger::EnterKeyAlways);
+        i.set_tooltip("line, line:col, +N/-N or N%");
+        let mut b = button::Button::default().with_label("Go");
+        row.fixed(&b, 60);
+        row.end();
+        win.end();
+        i.set_callback({
+            let mut win = win.clone();
+            move |i| {
+                let val = i.value();
+                let ed = STATE.with(|s| s.current_editor());
+                if let Some(mut ed) = ed {
+                    let buf = ed.buffer().unwrap();
+                    let current = buf.count_lines(0, ed.insert_position()) + 1;
+                    let total = buf.count_lines(0, buf.length()) + 1;
+                    if let Some((line, col)) = parse_goto(&val, current, total) {
+                        i.set_text_color(enums::Color::Foreground);
+                        goto_position(&mut ed, line, col);
+                        win.hide();
+                        ed.take_focus().ok();
+                    } else {
+                        i.set_text_color(enums::Color::Red);
+                    }
+                }
+            }
+        });
+        b.set_callback({
+            let mut i = i.clone();
+            move |_| i.do_callback()
+        });
+        win.handle(move |win, ev| match ev {
+            enums::Event::Show => {
+                i.set_value("");
+                i.set_text_color(enums::Color::Foreground);
+                i.take_focus().ok();
+                false
+            }
+            enums::Event::Hide => {
+                win.hide();
+                true
+            }
+            enums::Event::Close => {
+                win.hide();
+                true
+            }
+            _ => false,
+        });
+        Self { win }
+    }
+}
+
 pub struct ReplaceDialog {
     win: window::Window,
 }
 
+fn build_regex(pat: &str, use_regex: bool) -> Option<regex::Regex> {
+    if pat.is_empty() {
+        return None;
+    }
+    if use_regex {
+        regex::Regex::new(pat).ok()
+    } else {
+        regex::Regex::new(&regex::escape(pat)).ok()
+    }
+}
+
+fn expand(caps: &regex::Captures, rep: &str, use_regex: bool, out: &mut String) {
+    if use_regex {
+        caps.expand(rep, out);
+    } else {
+        out.push_str(rep);
+    }
+}
+
+/// Replaces every match of `re` in `start..end` with a single buffer edit,
+/// so that one undo reverts the whole operation.
+/// Returns the number of replacements and the new end of the range.
+fn replace_all_in(
+    buf: &mut text::TextBuffer,
+    re: &regex::Regex,
+    rep: &str,
+    use_regex: bool,
+    start: i32,
+    end: i32,
+) -> (usize, i32) {
+    let text = buf.text_range(start, end).unwrap_or_default();
+    let mut out = String::new();
+    let mut first = None;
+    let mut last = 0;
+    let mut count = 0;
+    for caps in re.captures_iter(&text) {
+        let m = caps.get(0).unwrap();
+        if first.is_none() {
+            first = Some(m.start());
+        } else {
+            out.push_str(&text[last..m.start()]);
+        }
+        expand(&caps, rep, use_regex, &mut out);
+        last = m.end();
+        count += 1;
+    }
+    if let Some(first) = first {
+        buf.replace(start + first as i32, start + last as i32, &out);
+        let delta = out.len() as i32 - (last - first) as i32;
+        (count, end + delta)
+    } else {
+        (0, end)
+    }
+}
+
+/// The range "In selection" replacements are limited to, in the editor it was taken from
+type Scope = (usize, i32, i32);
+
+/// Replaces the selection, or the empty match at the cursor, if it is a match,
+/// then selects the next match within `scope` if given.
+fn replace_next(
+    ed: &mut text::TextEditor,
+    buf: &mut text::TextBuffer,
+    re: &regex::Regex,
+    rep: &str,
+    use_regex: bool,
+    mut scope: Option<&mut Scope>,
+) -> bool {
+    let text = buf.text();
+    let (lo, hi) = scope
+        .as_ref()
+        .map_or((0, text.len()), |sc| (sc.1 as usize, sc.2 as usize));
+    let pos = ed.insert_position() as usize;
+    let (s, e) = buf
+        .selection_position()
+        .map_or((pos, pos), |(s, e)| (s as usize, e as usize));
+    let mut from = pos;
+    let mut replaced = false;
+    if lo <= s && e <= hi {
+        if let Some(caps) = re.captures_at(&text[lo..hi], s - lo) {
+            let m = caps.get(0).unwrap();
+            if m.start() + lo == s && m.end() + lo == e {
+                let mut out = String::new();
+                expand(&caps, rep, use_regex, &mut out);
+                buf.replace(s as i32, e as i32, &out);
+                if let Some(sc) = scope.as_mut() {
+                    sc.2 += out.len() as i32 - (e - s) as i32;
+                }
+                from = s + out.len();
+                // an empty match would be found again at the same place, step over a char
+                if s == e {
+                    from += text[e..].chars().next().map_or(1, char::len_utf8);
+                }
+                replaced = true;
+            }
+        }
+    }
+    let text = buf.text();
+    let (lo, hi) = scope
+        .as_ref()
+        .map_or((0, text.len()), |sc| (sc.1 as usize, sc.2 as usize));
+    let hay = &text[lo..hi];
+    let from = from.max(lo) - lo;
+    let next = (from <= hay.len())
+        .then(|| re.find_at(hay, from))
+        .flatten()
+        .or_else(|| re.find(hay));
+    if let Some(m) = next {
+        buf.select((lo + m.start()) as i32, (lo + m.end()) as i32);
+        ed.set_insert_position((lo + m.end()) as i32);
+        ed.show_insert_position();
+    } else {
+        buf.unselect();
+    }
+    replaced
+}
+
 impl ReplaceDialog {
     pub fn new() -> Self {
-        let mut win = window::Window::new(0, 0, 300, 80, "Replace").with_id("replace");
+        let mut win = window::Window::new(0, 0, 360, 80, "Replace").with_id("replace");
         win.set_border(false);
         let mut col = group::Flex::default_fill().column();
         col.set_margin(5);
@@ -115,21 +486,48 @@ impl ReplaceDialog {
         row.fixed(&f, 60);
         let mut search = input::Input::default();
         search.set_trigger(enums::CallbackTrigger::Changed);
+        let hist = add_history(&mut search, HistoryKind::Search);
+        row.fixed(&hist, 20);
         let mut reg = button::ToggleButton::default().with_label(".*");
         reg.set_selection_color(reg.color().lighter());
         reg.set_tooltip("Use regex");
         row.fixed(&reg, 30);
+        let mut in_sel = button::ToggleButton::default().with_label("[ ]");
+        in_sel.set_selection_color(in_sel.color().lighter());
+        in_sel.set_tooltip("In selection");
+        row.fixed(&in_sel, 30);
         row.end();
         let mut row = group::Flex::default();
         let f = frame::Frame::default().with_label("Replace:");
         row.fixed(&f, 60);
-        let replace = input::Input::default();
-        let mut b = button::Button::default().with_label("@>");
-        b.set_tooltip("Apply");
-        row.fixed(&b, 30);
+        let mut replace = input::Input::default();
+        let hist = add_history(&mut replace, HistoryKind::Replace);
+        row.fixed(&hist, 20);
+        let mut next = button::Button::default().with_label("@>");
+        next.set_tooltip("Replace");
+        row.fixed(&next, 30);
+        let mut all = button::Button::default().with_label("@>>");
+        all.set_tooltip("Replace All");
+        row.fixed(&all, 30);
         row.end();
         col.end();
         win.end();
+        let scope: Rc<RefCell<Option<Scope>>> = Rc::from(RefCell::from(None));
+        in_sel.set_callback({
+            let scope = scope.clone();
+            move |b| {
+                // taken from the current selection, which later replacements change
+                *scope.borrow_mut() = if b.value() {
+                    STATE.with(|s| {
+                        let ed = s.current_editor()?;
+                        let (start, end) = ed.buffer()?.selection_position()?;
+                        Some((ed.as_widget_ptr() as usize, start, end))
+                    })
+                } else {
+                    None
+                };
+            }
+        });
         search.set_callback({
             let reg = reg.clone();
             move |i| {
@@ -142,32 +540,82 @@ impl ReplaceDialog {
                 }
             }
         });
-        b.set_callback(move |_| {
-            let search = search.value();
-            let replace = replace.value();
-            let reg_val = reg.value();
-            if reg_val && regex::Regex::new(&search).is_err() {
-                return;
+        next.set_callback({
+            let search = search.clone();
+            let replace = replace.clone();
+            let reg = reg.clone();
+            let in_sel = in_sel.clone();
+            let scope = scope.clone();
+            move |_| {
+                let reg_val = reg.value();
+                let re = match build_regex(&search.value(), reg_val) {
+                    Some(re) => re,
+                    None => return,
+                };
+                let pat = search.value();
+                let rep = replace.value();
+                let in_sel = in_sel.value();
+                let scope = scope.clone();
+                STATE.with(move |s| {
+                    s.search_history.push(&pat);
+                    s.replace_history.push(&rep);
+                    if let (Some(mut ed), Some(mut buf)) = (s.editable_editor(), s.buf()) {
+                        let mut scope = scope.borrow_mut();
+                        let id = ed.as_widget_ptr() as usize;
+                        if !in_sel {
+                            *scope = None;
+                        } else if scope.map_or(true, |sc| sc.0 != id) {
+                            *scope = buf
+                                .selection_position()
+                                .map(|(start, end)| (id, start, end));
+                        }
+                        if replace_next(&mut ed, &mut buf, &re, &rep, reg_val, scope.as_mut()) {
+                            s.was_modified(true);
+                        }
+                    }
+                });
             }
-            STATE.with({
-                move |s| {
-                    if let Some(buf) = s.buf().as_mut() {
-                        let text = buf.text();
-                        if reg_val {
-                            if let Ok(re) = regex::Regex::new(&search) {
-                                let ntext = re.replace(&text, &replace);
-                                buf.set_text(&ntext);
+        });
+        all.set_callback({
+            let scope = scope.clone();
+            move |_| {
+                let reg_val = reg.value();
+                let re = match build_regex(&search.value(), reg_val) {
+                    Some(re) => re,
+                    None => return,
+                };
+                let pat = search.value();
+                let rep = replace.value();
+                let in_sel = in_sel.value();
+                let scope = scope.clone();
+                STATE.with(move |s| {
+                    s.search_history.push(&pat);
+                    s.replace_history.push(&rep);
+                    if let (Some(ed), Some(mut buf)) = (s.editable_editor(), s.buf()) {
+                        let id = ed.as_widget_ptr() as usize;
+                        let mut scope = scope.borrow_mut();
+                        let (start, end) = match (*scope, buf.selection_position()) {
+                            (Some((sid, start, end)), _) if in_sel && sid == id => (start, end),
+                            (_, Some(sel)) if in_sel => sel,
+                            _ => (0, buf.length()),
+                        };
+                        let (count, end) = replace_all_in(&mut buf, &re, &rep, reg_val, start, end);
+                        if count > 0 {
+                            if in_sel {
+                                buf.select(start, end);
+                                *scope = Some((id, start, end));
                             }
-                        } else {
-                            let ntext = text.replace(&search, &replace);
-                            buf.set_text(&ntext);
+                            s.was_modified(true);
                         }
-                        s.was_modified(true);
                     }
-                }
-            });
+                });
+            }
         });
-        win.handle(|win, ev| match ev {
+        win.handle(move |win, ev| match ev {
+            enums::Event::Show => {
+                scope.replace(None);
+                false
+            }
             enums::Event::Hide => {
                 win.hide();
                 true
@@ -182,6 +630,263 @@ impl ReplaceDialog {
     }
 }
 
+const MAX_RESULTS: usize = 200;
+const PREVIEW_BYTES: usize = 16 * 1024;
+
+struct PaletteItem<T> {
+    value: T,
+    label: String,
+    hint: String,
+}
+
+struct PaletteData<T> {
+    items: Vec<PaletteItem<T>>,
+    shown: Vec<usize>,
+}
+
+impl<T: Clone> PaletteData<T> {
+    fn filter(&mut self, pat: &str, br: &mut browser::HoldBrowser) {
+        let mut scored: Vec<_> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| utils::fuzzy_score(pat, &item.label).map(|sc| (sc, i)))
+            .collect();
+        scored.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then(self.items[a.1].label.cmp(&self.items[b.1].label))
+        });
+        self.shown = scored.into_iter().take(MAX_RESULTS).map(|e| e.1).collect();
+        br.clear();
+        for i in &self.shown {
+            let item = &self.items[*i];
+            if item.hint.is_empty() {
+                br.add(&item.label);
+            } else {
+                br.add(&format!("{}\t{}", item.label, item.hint));
+            }
+        }
+        if !self.shown.is_empty() {
+            br.select(1);
+        }
+    }
+
+    fn selected(&self, br: &browser::HoldBrowser) -> Option<T> {
+        let idx = br.value();
+        if idx < 1 {
+            return None;
+        }
+        self.shown
+            .get(idx as usize - 1)
+            .map(|i| self.items[*i].value.clone())
+    }
+}
+
+/// Builds the borderless filter input + list window shared by the palettes.
+/// `load` refreshes the items whenever the window is shown, `accept` runs the chosen item,
+/// and `preview`, if passed, shows the highlighted item next to the list.
+fn palette<T: Clone + 'static>(
+    id: &str,
+    title: &str,
+    (w, h): (i32, i32),
+    load: fn() -> Vec<PaletteItem<T>>,
+    accept: fn(T),
+    preview: Option<fn(Option<T>, &mut text::TextBuffer)>,
+) -> window::Window {
+    let data = Rc::from(RefCell::from(PaletteData {
+        items: vec![],
+        shown: vec![],
+    }));
+    let mut win = window::Window::new(0, 0, w, h, None)
+        .with_label(title)
+        .with_id(id);
+    win.set_border(false);
+    let mut col = group::Flex::default_fill().column();
+    col.set_margin(5);
+    let mut inp = input::Input::default();
+    inp.set_trigger(enums::CallbackTrigger::Changed);
+    col.fixed(&inp, 30);
+    let mut row = group::Flex::default();
+    row.set_pad(5);
+    let mut br = browser::HoldBrowser::default();
+    br.set_format_char('\0');
+    let mut pbuf = text::TextBuffer::default();
+    if preview.is_some() {
+        row.fixed(&br, 250);
+        let mut disp = text::TextDisplay::default();
+        disp.set_buffer(pbuf.clone());
+        disp.set_text_font(enums::Font::Courier);
+        disp.set_text_size(12);
+    } else {
+        br.set_column_widths(&[440, 140]);
+    }
+    row.end();
+    col.end();
+    win.end();
+    let update_preview = {
+        let data = data.clone();
+        let br = br.clone();
+        move || {
+            if let Some(f) = preview {
+                f(data.borrow().selected(&br), &mut pbuf);
+            }
+        }
+    };
+    let do_accept = {
+        let data = data.clone();
+        let br = br.clone();
+        let mut win = win.clone();
+        move || {
+            let sel = data.borrow().selected(&br);
+            if let Some(sel) = sel {
+                win.hide();
+                accept(sel);
+            }
+        }
+    };
+    inp.set_callback({
+        let data = data.clone();
+        let mut br = br.clone();
+        let mut update_preview = update_preview.clone();
+        move |i| {
+            data.borrow_mut().filter(&i.value(), &mut br);
+            update_preview();
+        }
+    });
+    inp.handle({
+        let mut br = br.clone();
+        let mut update_preview = update_preview.clone();
+        let mut do_accept = do_accept.clone();
+        move |_, ev| {
+            if ev != enums::Event::KeyDown {
+                return false;
+            }
+            let idx = br.value();
+            match app::event_key() {
+                enums::Key::Up if idx > 1 => br.select(idx - 1),
+                enums::Key::Down if idx < br.size() => br.select(idx + 1),
+                enums::Key::Enter | enums::Key::KPEnter => {
+                    do_accept();
+                    return true;
+                }
+                enums::Key::Up | enums::Key::Down => (),
+                _ => return false,
+            }
+            br.make_visible(br.value());
+            update_preview();
+            true
+        }
+    });
+    br.set_callback({
+        let mut update_preview = update_preview.clone();
+        let mut do_accept = do_accept;
+        move |_| {
+            if app::event_clicks() {
+                do_accept();
+            } else {
+                update_preview();
+            }
+        }
+    });
+    let mut update_preview = update_preview;
+    win.handle(move |win, ev| match ev {
+        enums::Event::Show => {
+            let mut data = data.borrow_mut();
+            data.items = load();
+            inp.set_value("");
+            data.filter("", &mut br);
+            drop(data);
+            update_preview();
+            inp.take_focus().ok();
+            false
+        }
+        enums::Event::Hide => {
+            win.hide();
+            true
+        }
+        enums::Event::Close => {
+            win.hide();
+            true
+        }
+        _ => false,
+    });
+    win
+}
+
+pub struct QuickOpen {
+    win: window::Window,
+}
+
+impl QuickOpen {
+    pub fn new() -> Self {
+        let win = palette::<PathBuf>(
+            "quick_open",
+            "Quick Open",
+            (600, 400),
+            || {
+                let root = STATE.with(|s| s.current_dir.clone());
+                utils::project_files(&root)
+                    .into_iter()
+                    .map(|p| {
+                        let label = p.strip_prefix(&root).unwrap_or(&p).display().to_string();
+                        PaletteItem {
+                            value: p,
+                            label,
+                            hint: String::new(),
+                        }
+                    })
+                    .collect()
+            },
+            |path: PathBuf| STATE.with(move |s| s.append(Some(path.clone()))),
+            Some(|path: Option<PathBuf>, buf: &mut text::TextBuffer| {
+                if let Some(path) = path {
+                    let bytes = fs::read(path).unwrap_or_default();
+                    let bytes = &bytes[..bytes.len().min(PREVIEW_BYTES)];
+                    if bytes.contains(&0) {
+                        buf.set_text("<binary file>");
+                    } else {
+                        buf.set_text(&String::from_utf8_lossy(bytes));
+                    }
+                } else {
+                    buf.set_text("");
+                }
+            }),
+        );
+        Self { win }
+    }
+}
+
+pub struct CommandPalette {
+    win: window::Window,
+}
+
+impl CommandPalette {
+    pub fn new() -> Self {
+        let win = palette::<&'static str>(
+            "command_palette",
+            "Command Palette",
+            (600, 300),
+            || {
+                commands::COMMANDS
+                    .iter()
+                    .filter(|c| c.id != "view.command_palette")
+                    .map(|c| PaletteItem {
+                        value: c.id,
+                        label: c.title(),
+                        hint: commands::shortcut_label(c.shortcut),
+                    })
+                    .collect()
+            },
+            // let the palette hide before running, the command might show another window
+            |id: &'static str| {
+                app::add_timeout3(0.01, move |_| commands::run(id));
+            },
+            None,
+        );
+        Self { win }
+    }
+}
+
 pub struct ImageDialog {
     win: window::Window,
 }
@@ -197,3 +902,195 @@ impl ImageDialog {
         Self { win }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_goto_lines_and_cols() {
+        assert_eq!(parse_goto("12", 1, 100), Some((12, 1)));
+        assert_eq!(parse_goto(" 12 : 5 ", 1, 100), Some((12, 5)));
+        assert_eq!(parse_goto(":7", 40, 100), Some((40, 7)));
+        assert_eq!(parse_goto("12:0", 1, 100), Some((12, 1)));
+        assert_eq!(parse_goto("abc", 1, 100), None);
+        assert_eq!(parse_goto("12:x", 1, 100), None);
+    }
+
+    #[test]
+    fn parse_goto_relative_and_percent() {
+        assert_eq!(parse_goto("+5", 10, 100), Some((15, 1)));
+        assert_eq!(parse_goto("-5", 10, 100), Some((5, 1)));
+        assert_eq!(parse_goto("50%", 1, 200), Some((100, 1)));
+        assert_eq!(parse_goto("0%", 50, 200), Some((1, 1)));
+    }
+
+    #[test]
+    fn parse_goto_clamps_out_of_range_input() {
+        assert_eq!(parse_goto("500", 1, 100), Some((100, 1)));
+        assert_eq!(parse_goto("+2147483647", 10, 100), Some((100, 1)));
+        assert_eq!(parse_goto("-2147483647", -10, 100), Some((1, 1)));
+        assert_eq!(parse_goto("1e300%", 1, 100), Some((100, 1)));
+        assert_eq!(parse_goto("-50%", 1, 100), Some((1, 1)));
+        assert_eq!(parse_goto("NaN%", 1, 100), None);
+        assert_eq!(parse_goto("5", 1, 0), Some((1, 1)));
+    }
+
+    fn editor(text: &str) -> (text::TextEditor, text::TextBuffer) {
+        let mut buf = text::TextBuffer::default();
+        buf.set_text(text);
+        let mut ed = text::TextEditor::default();
+        ed.set_buffer(buf.clone());
+        (ed, buf)
+    }
+
+    fn select(ed: &mut text::TextEditor, buf: &mut text::TextBuffer, start: i32, end: i32) {
+        buf.select(start, end);
+        ed.set_insert_position(end);
+    }
+
+    fn replace_all(text: &str, pat: &str, rep: &str, use_regex: bool) -> (String, usize) {
+        let mut buf = text::TextBuffer::default();
+        buf.set_text(text);
+        let re = build_regex(pat, use_regex).unwrap();
+        let (count, end) = replace_all_in(&mut buf, &re, rep, use_regex, 0, text.len() as i32);
+        assert_eq!(end, buf.length());
+        (buf.text(), count)
+    }
+
+    #[test]
+    fn replace_all_literal_and_regex() {
+        assert_eq!(replace_all("a.b a.b", "a.b", "x", false), ("x x".into(), 2));
+        assert_eq!(
+            replace_all("axb a.b", "a.b", "x", false),
+            ("axb x".into(), 1)
+        );
+        assert_eq!(
+            replace_all("k=1 j=2", r"(\w)=(\d)", "$2=$1", true),
+            ("1=k 2=j".into(), 2)
+        );
+        // not expanded without regex
+        assert_eq!(replace_all("a", "a", "$0", false), ("$0".into(), 1));
+        assert_eq!(replace_all("abc", "z", "y", false), ("abc".into(), 0));
+    }
+
+    #[test]
+    fn replace_all_empty_matches_and_replacements() {
+        assert_eq!(replace_all("ab", "x*", "-", true), ("-a-b-".into(), 3));
+        assert_eq!(replace_all("a, b, c", ", ", "", false), ("abc".into(), 2));
+        // the replacement isn't searched again
+        assert_eq!(replace_all("a a", "a", "aa", false), ("aa aa".into(), 2));
+    }
+
+    #[test]
+    fn replace_all_in_range_and_non_ascii() {
+        let mut buf = text::TextBuffer::default();
+        buf.set_text("é é é");
+        let re = build_regex("é", false).unwrap();
+        // only the middle one, bytes 3..5
+        let (count, end) = replace_all_in(&mut buf, &re, "ee", false, 3, 5);
+        assert_eq!((buf.text().as_str(), count, end), ("é ee é", 1, 5));
+        assert_eq!(replace_all("日本語", "本", "-", false), ("日-語".into(), 1));
+    }
+
+    #[test]
+    fn replace_next_replaces_selected_match_and_selects_next() {
+        let (mut ed, mut buf) = editor("foo bar foo");
+        let re = build_regex("foo", false).unwrap();
+        // nothing selected, only selects the next match
+        ed.set_insert_position(1);
+        assert!(!replace_next(&mut ed, &mut buf, &re, "x", false, None));
+        assert_eq!(buf.selection_position(), Some((8, 11)));
+        assert!(replace_next(&mut ed, &mut buf, &re, "x", false, None));
+        assert_eq!(buf.text(), "foo bar x");
+        // wraps around
+        assert_eq!(buf.selection_position(), Some((0, 3)));
+        assert!(replace_next(&mut ed, &mut buf, &re, "x", false, None));
+        assert_eq!(buf.text(), "x bar x");
+        assert_eq!(buf.selection_position(), None);
+    }
+
+    #[test]
+    fn replace_next_does_not_loop_on_its_replacement() {
+        let (mut ed, mut buf) = editor("a b a");
+        let re = build_regex("a", false).unwrap();
+        select(&mut ed, &mut buf, 0, 1);
+        assert!(replace_next(&mut ed, &mut buf, &re, "aa", false, None));
+        assert_eq!(buf.text(), "aa b a");
+        assert_eq!(buf.selection_position(), Some((5, 6)));
+        assert!(replace_next(&mut ed, &mut buf, &re, "aa", false, None));
+        assert_eq!(buf.text(), "aa b aa");
+    }
+
+    #[test]
+    fn replace_next_steps_past_empty_matches() {
+        let (mut ed, mut buf) = editor("ab");
+        let re = build_regex("x*", true).unwrap();
+        ed.set_insert_position(0);
+        assert!(replace_next(&mut ed, &mut buf, &re, "-", true, None));
+        assert_eq!(buf.text(), "-ab");
+        assert_eq!(ed.insert_position(), 2);
+        assert!(replace_next(&mut ed, &mut buf, &re, "-", true, None));
+        assert_eq!(buf.text(), "-a-b");
+        assert_eq!(ed.insert_position(), 4);
+    }
+
+    #[test]
+    fn replace_next_with_empty_replacement() {
+        let (mut ed, mut buf) = editor("a,b,c");
+        let re = build_regex(",", false).unwrap();
+        select(&mut ed, &mut buf, 1, 2);
+        assert!(replace_next(&mut ed, &mut buf, &re, "", false, None));
+        assert_eq!(buf.text(), "ab,c");
+        assert_eq!(buf.selection_position(), Some((2, 3)));
+    }
+
+    #[test]
+    fn replace_next_stays_in_scope() {
+        let (mut ed, mut buf) = editor("foo foo foo");
+        let re = build_regex("foo", false).unwrap();
+        let mut scope: Scope = (ed.as_widget_ptr() as usize, 4, 11);
+        select(&mut ed, &mut buf, 4, 7);
+        assert!(replace_next(
+            &mut ed,
+            &mut buf,
+            &re,
+            "quux",
+            false,
+            Some(&mut scope)
+        ));
+        assert_eq!(buf.text(), "foo quux foo");
+        assert_eq!(scope.2, 12);
+        assert_eq!(buf.selection_position(), Some((9, 12)));
+        assert!(replace_next(
+            &mut ed,
+            &mut buf,
+            &re,
+            "quux",
+            false,
+            Some(&mut scope)
+        ));
+        assert_eq!(buf.text(), "foo quux quux");
+        // the first one is out of scope
+        assert_eq!(buf.selection_position(), None);
+        assert!(!replace_next(
+            &mut ed,
+            &mut buf,
+            &re,
+            "quux",
+            false,
+            Some(&mut scope)
+        ));
+        assert_eq!(buf.text(), "foo quux quux");
+    }
+
+    #[test]
+    fn replace_next_non_ascii() {
+        let (mut ed, mut buf) = editor("é é");
+        let re = build_regex("é", false).unwrap();
+        select(&mut ed, &mut buf, 0, 2);
+        assert!(replace_next(&mut ed, &mut buf, &re, "e", false, None));
+        assert_eq!(buf.text(), "e é");
+        assert_eq!(buf.selection_position(), Some((2, 4)));
+    }
+}
@@ -0,0 +1,189 @@
+/// Beyond this many edits two texts are treated as one big change
+const MAX_EDITS: isize = 2000;
+
+/// A replaced range of lines, either side may be empty
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+impl Hunk {
+    pub fn is_addition(&self) -> bool {
+        self.old_len == 0
+    }
+
+    pub fn is_deletion(&self) -> bool {
+        self.new_len == 0
+    }
+}
+
+/// Diffs `a` against `b` using Myers' algorithm
+pub fn diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Hunk> {
+    let pre = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suf = a[pre..]
+        .iter()
+        .rev()
+        .zip(b[pre..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a2, b2) = (&a[pre..a.len() - suf], &b[pre..b.len() - suf]);
+    let matches = myers(a2, b2).unwrap_or_default();
+    let mut hunks = vec![];
+    let (mut i, mut j) = (0, 0);
+    for (mi, mj) in matches.into_iter().chain(Some((a2.len(), b2.len()))) {
+        if mi > i || mj > j {
+            hunks.push(Hunk {
+                old_start: pre + i,
+                old_len: mi - i,
+                new_start: pre + j,
+                new_len: mj - j,
+            });
+        }
+        i = mi + 1;
+        j = mj + 1;
+    }
+    hunks
+}
+
+/// Returns the matching (a, b) index pairs of a shortest edit script,
+/// or None if it takes more than MAX_EDITS edits
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Option<Vec<(usize, usize)>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m).min(MAX_EDITS);
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    // trace[d] holds v[-d..=d] as it was before step d
+    let mut trace: Vec<Vec<isize>> = vec![];
+    let mut done = false;
+    for d in 0..=max {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                done = true;
+                break;
+            }
+        }
+        if done {
+            break;
+        }
+    }
+    if !done {
+        return None;
+    }
+    let mut matches = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let (prev_x, prev_y) = if d == 0 {
+            (0, 0)
+        } else {
+            (at(prev_k), at(prev_k) - prev_k)
+        };
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            matches.push((x as usize, y as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    matches.reverse();
+    Some(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hunk(old_start: usize, old_len: usize, new_start: usize, new_len: usize) -> Hunk {
+        Hunk {
+            old_start,
+            old_len,
+            new_start,
+            new_len,
+        }
+    }
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    /// Rebuilds `b` from `a` and the hunks of `diff(a, b)`
+    fn apply(a: &[char], b: &[char], hunks: &[Hunk]) -> Vec<char> {
+        let mut out = vec![];
+        let mut i = 0;
+        for h in hunks {
+            out.extend_from_slice(&a[i..h.old_start]);
+            out.extend_from_slice(&b[h.new_start..h.new_start + h.new_len]);
+            i = h.old_start + h.old_len;
+        }
+        out.extend_from_slice(&a[i..]);
+        out
+    }
+
+    #[test]
+    fn diff_of_equal_texts_is_empty() {
+        assert!(diff(&chars("abc"), &chars("abc")).is_empty());
+        assert!(diff::<char>(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn diff_finds_single_changes() {
+        assert_eq!(diff(&chars("abc"), &chars("axbc")), [hunk(1, 0, 1, 1)]);
+        assert_eq!(diff(&chars("abc"), &chars("ac")), [hunk(1, 1, 1, 0)]);
+        assert_eq!(diff(&chars("abc"), &chars("axc")), [hunk(1, 1, 1, 1)]);
+        assert_eq!(diff(&chars(""), &chars("xy")), [hunk(0, 0, 0, 2)]);
+        assert_eq!(diff(&chars("xy"), &chars("")), [hunk(0, 2, 0, 0)]);
+    }
+
+    #[test]
+    fn diff_finds_separate_hunks() {
+        assert_eq!(
+            diff(&chars("abcdefg"), &chars("axcdeyg")),
+            [hunk(1, 1, 1, 1), hunk(5, 1, 5, 1)]
+        );
+    }
+
+    #[test]
+    fn diff_hunks_turn_a_into_b() {
+        for (a, b) in [
+            ("abcabba", "cbabac"),
+            ("the quick brown fox", "a quick brown dog jumps"),
+            ("aaaa", "aabaa"),
+            ("xyz", "zyx"),
+        ] {
+            let (a, b) = (chars(a), chars(b));
+            let hunks = diff(&a, &b);
+            assert_eq!(apply(&a, &b, &hunks), b);
+        }
+    }
+
+    #[test]
+    fn diff_gives_up_on_too_many_edits() {
+        let a: Vec<_> = (0..1001).collect();
+        let b: Vec<_> = (1001..2002).collect();
+        assert_eq!(diff(&a, &b), [hunk(0, 1001, 0, 1001)]);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{
+    cbs, diff, git, gui,
+    state::{self, MyBuffer, STATE},
+};
+use fltk::{enums::*, prelude::*, *};
+use std::{cell::Cell, fs, iter, path::Path, rc::Rc};
+
+// styles of the diff panes
+const NORMAL: char = 'A';
+const REMOVED_LINE: char = 'B';
+const REMOVED: char = 'C';
+const ADDED_LINE: char = 'D';
+const ADDED: char = 'E';
+const FILLER: char = 'F';
+
+fn styles() -> Vec<text::StyleTableEntryExt> {
+    let entry = |bgcolor: u32, attr: text::TextAttr| text::StyleTableEntryExt {
+        color: Color::Foreground,
+        font: Font::Courier,
+        size: app::font_size(),
+        attr,
+        bgcolor: Color::from_hex(bgcolor),
+    };
+    vec![
+        entry(0x282c34, text::TextAttr::None),
+        entry(0x4b2a2f, text::TextAttr::BgColorExt),
+        entry(0x8a3a42, text::TextAttr::BgColor),
+        entry(0x2f4431, text::TextAttr::BgColorExt),
+        entry(0x3f7040, text::TextAttr::BgColor),
+        entry(0x21252b, text::TextAttr::BgColorExt),
+    ]
+}
+
+/// The text of one pane, padded so that unchanged lines face each other
+#[derive(Default)]
+struct Pane {
+    text: String,
+    style: Vec<u8>,
+}
+
+impl Pane {
+    fn push(&mut self, line: &str, style: char) {
+        self.text.push_str(line);
+        self.style
+            .extend(iter::repeat(style as u8).take(line.len()));
+        if !line.ends_with('\n') {
+            self.text.push('\n');
+            self.style.push(style as u8);
+        }
+    }
+
+    /// Pushes `line`, highlighting the bytes in `changed` with `inline`
+    fn push_changed(&mut self, line: &str, changed: &[(usize, usize)], style: char, inline: char) {
+        let start = self.style.len();
+        self.push(line, style);
+        for &(from, len) in changed {
+            self.style[start + from..start + from + len].fill(inline as u8);
+        }
+    }
+
+    fn filler(&mut self) {
+        self.push("", FILLER);
+    }
+}
+
+/// Lays out `old` and `new` side by side
+fn align(old: &str, new: &str) -> (Pane, Pane) {
+    let old: Vec<_> = old.lines().collect();
+    let new: Vec<_> = new.lines().collect();
+    let (mut left, mut right) = (Pane::default(), Pane::default());
+    let end = diff::Hunk {
+        old_start: old.len(),
+        old_len: 0,
+        new_start: new.len(),
+        new_len: 0,
+    };
+    let mut j = 0;
+    for h in diff::diff(&old, &new).into_iter().chain(Some(end)) {
+        for line in &new[j..h.new_start] {
+            left.push(line, NORMAL);
+            right.push(line, NORMAL);
+        }
+        for k in 0..h.old_len.max(h.new_len) {
+            let a = old[h.old_start..h.old_start + h.old_len].get(k);
+            let b = new[h.new_start..h.new_start + h.new_len].get(k);
+            match (a, b) {
+                (Some(a), Some(b)) => {
+                    let (a_changed, b_changed): (Vec<_>, Vec<_>) =
+                        diff::diff(a.as_bytes(), b.as_bytes())
+                            .into_iter()
+                            .map(|c| ((c.old_start, c.old_len), (c.new_start, c.new_len)))
+                            .unzip();
+                    left.push_changed(a, &a_changed, REMOVED_LINE, REMOVED);
+                    right.push_changed(b, &b_changed, ADDED_LINE, ADDED);
+                }
+                (Some(a), None) => {
+                    left.push(a, REMOVED_LINE);
+                    right.filler();
+                }
+                (None, Some(b)) => {
+                    left.filler();
+                    right.push(b, ADDED_LINE);
+                }
+                (None, None) => (),
+            }
+        }
+        j = h.new_start + h.new_len;
+    }
+    (left, right)
+}
+
+/// The first visible line of `ed`, starting at 1
+fn top_line(ed: &text::TextEditor) -> i32 {
+    let buf = ed.buffer().unwrap();
+    let x = ed.x() + ed.linenumber_width() + 2;
+    let pos = ed.xy_to_position(x, ed.y() + 2, text::PositionType::Cursor);
+    buf.count_lines(0, pos) + 1
+}
+
+/// An editor showing `pane`, and its style buffer
+fn pane(pane: Pane) -> (text::TextEditor, text::TextBuffer) {
+    let mut ed = text::TextEditor::default();
+    gui::init_editor(&mut ed);
+    let mut buf = text::TextBuffer::default();
+    buf.set_tab_distance(4);
+    buf.set_text(&pane.text);
+    ed.set_buffer(buf);
+    let mut sbuf = text::TextBuffer::default();
+    sbuf.set_text(&String::from_utf8_lossy(&pane.style));
+    ed.set_highlight_data_ext(sbuf.clone(), styles());
+    (ed, sbuf)
+}
+
+/// Scrolls `other` along with `ed` until the tab is `closed`
+fn sync_scroll(ed: &mut text::TextEditor, other: &text::TextEditor, closed: &Rc<Cell<bool>>) {
+    ed.handle({
+        let other = other.clone();
+        let closed = closed.clone();
+        move |e, ev| {
+            if matches!(
+                ev,
+                Event::MouseWheel | Event::Push | Event::Drag | Event::Release | Event::KeyDown
+            ) {
+                let e = e.clone();
+                let mut other = other.clone();
+                let closed = closed.clone();
+                // let the editor scroll first
+                app::add_timeout3(0.0, move |_| {
+                    if closed.get() {
+                        return;
+                    }
+                    let line = top_line(&e);
+                    if top_line(&other) != line {
+                        other.scroll(line, 0);
+                    }
+                });
+            }
+            gui::blocks_edit(ev)
+        }
+    });
+}
+
+/// Opens a read-only tab showing `old` and `new` side by side
+pub fn open(old_title: &str, old: &str, new_title: &str, new: &str) {
+    let mut tabs: group::Tabs = app::widget_from_id("tabs").unwrap();
+    let (left, right) = align(old, new);
+    let id = state::next_id();
+    tabs.begin();
+    let mut edrow = group::Flex::default()
+        .row()
+        .with_label(&format!("\t{} \u{2194} {}", old_title, new_title))
+        .with_id(&id);
+    edrow.set_pad(2);
+    edrow.set_trigger(CallbackTrigger::Closed);
+    let (mut left, left_styles) = pane(left);
+    let (mut right, right_styles) = pane(right);
+    let closed = Rc::new(Cell::new(false));
+    sync_scroll(&mut left, &right, &closed);
+    sync_scroll(&mut right, &left, &closed);
+    edrow.set_callback({
+        let mut eds = [left.clone(), right.clone()];
+        move |g| {
+            if app::callback_reason() != CallbackReason::Closed {
+                return;
+            }
+            closed.set(true);
+            for ed in eds.iter_mut() {
+                ed.handle(|_, _| false);
+            }
+            // frees the left buffer along with the tab's state
+            cbs::tab_close_cb(g);
+            let right_buf = eds[1].buffer().unwrap();
+            unsafe {
+                text::TextBuffer::delete(right_buf);
+                text::TextBuffer::delete(left_styles.clone());
+                text::TextBuffer::delete(right_styles.clone());
+            }
+        }
+    });
+    edrow.end();
+    tabs.end();
+    tabs.auto_layout();
+    tabs.set_value(&edrow).ok();
+    tabs.redraw();
+    let mybuf = MyBuffer {
+        modified: false,
+        id,
+        buf: left.buffer().unwrap(),
+        current_file: None,
+        gutter: None,
+        read_only: true,
+    };
+    STATE.with(move |s| {
+        s.map.insert(left.as_widget_ptr() as usize, mybuf.clone());
+    });
+}
+
+fn name(p: &Path) -> String {
+    p.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+fn read(p: &Path) -> Option<String> {
+    match fs::read_to_string(p) {
+        Ok(s) => Some(s),
+        Err(e) => {
+            dialog::alert_default(&format!("Failed to read {}: {}", p.display(), e));
+            None
+        }
+    }
+}
+
+pub fn compare_files(old: &Path, new: &Path) {
+    if let (Some(a), Some(b)) = (read(old), read(new)) {
+        open(&name(old), &a, &name(new), &b);
+    }
+}
+
+/// Compares the current buffer with its file on disk
+pub fn compare_with_saved() {
+    let current = STATE.with(|s| Some((s.current_file()?, s.buf()?.text())));
+    if let Some((path, text)) = current {
+        if let Some(saved) = read(&path) {
+            let name = name(&path);
+            open(&format!("{} (saved)", name), &saved, &name, &text);
+        }
+    }
+}
+
+/// Compares the current buffer with its version at HEAD
+pub fn compare_with_head() {
+    let current = STATE.with(|s| Some((s.current_file()?, s.buf()?.text())));
+    if let Some((path, text)) = current {
+        let name = name(&path);
+        let head = path
+            .parent()
+            .and_then(|dir| git::run_git(dir, &["show", &format!("HEAD:./{}", name)]));
+        match head {
+            Some(head) => open(
+                &format!("{} (HEAD)", name),
+                &String::from_utf8_lossy(&head),
+                &name,
+                &text,
+            ),
+            None => dialog::alert_default(&format!("{} is not tracked by git", name)),
+        }
+    }
+}
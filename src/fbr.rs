@@ -1,74 +1,628 @@
 #![allow(clippy::single_match)]
 
-use crate::{cbs, state::STATE, utils};
-use fltk::{enums::*, prelude::*, *};
-use notify::{event::EventKind, Event, RecursiveMode, Watcher};
+use crate::{cbs, commands, diffview, git, state::STATE, utils};
+use fltk::{enums::*, prelude::*, utils::oncelock::Lazy, *};
+use notify::{
+    event::{EventKind, ModifyKind, RenameMode},
+    RecursiveMode, Watcher,
+};
 use std::{
-    env,
+    collections::HashSet,
+    fs,
     path::{Path, PathBuf},
+    sync::Mutex,
 };
 
-pub fn menu_cb(m: &mut impl MenuExt) {
-    if let Ok(mpath) = m.item_pathname(None) {
-        match mpath.as_str() {
-            "/New File...\t" => cbs::new_file(),
-            "/New Dir...\t" => cbs::new_dir(),
-            _ => (),
+#[cfg(feature = "term")]
+use crate::terminal;
+
+// child of not yet loaded directories, so that they can be expanded
+const PLACEHOLDER: &str = "\u{2026}";
+
+// the file picked with "Select for Compare"
+static COMPARE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+pub fn init_menu(m: &mut (impl MenuExt + 'static)) {
+    commands::add_item(m, "file.new_file", "New File...\t");
+    commands::add_item(m, "file.new_dir", "New Dir...\t");
+    commands::add_item(m, "file.rename", "Rename...\t");
+    commands::add_item(m, "file.duplicate", "Duplicate\t");
+    commands::add_item(m, "file.delete", "Delete\t");
+    commands::add_item(m, "file.select_for_compare", "Select for Compare\t");
+    commands::add_item(m, "file.compare_with_selected", "Compare with Selected\t");
+    #[cfg(feature = "term")]
+    commands::add_item(m, "terminal.open_here", "Open Terminal Here\t");
+    commands::add_item(m, "file.copy_path", "Copy Path\t");
+    commands::add_item(m, "file.copy_relative_path", "Copy Relative Path\t");
+}
+
+#[cfg(feature = "term")]
+pub fn open_terminal_here() {
+    terminal::open_here(&selected_dir());
+}
+
+fn alert(msg: &str, e: impl std::fmt::Display) {
+    dialog::alert_default(&format!("{}: {}", msg, e));
+}
+
+/// Reloads `dir` in the browser if it was already expanded
+pub fn refresh(dir: &Path) {
+    let root = STATE.with(|s| s.current_dir.clone());
+    let mut t: tree::Tree = app::widget_from_id("fbr").unwrap();
+    if let Some(item) = find_item(&t, &root, dir) {
+        if is_loaded(&item) {
+            populate(&mut t, &item, dir);
         }
     }
 }
 
-pub fn init_menu(m: &mut (impl MenuExt + 'static)) {
-    m.add(
-        "New File...\t",
-        Shortcut::Ctrl | 'n',
-        menu::MenuFlag::Normal,
-        menu_cb,
-    );
-    m.add(
-        "New Dir...\t",
-        Shortcut::Ctrl | Shortcut::Shift | 'n',
-        menu::MenuFlag::Normal,
-        menu_cb,
-    );
-}
-
-pub fn fbr_cb(f: &mut browser::FileBrowser, watcher: &mut dyn Watcher) {
-    if let Some(path) = f.text(f.value()) {
-        let path = PathBuf::from(path);
-        if path.exists() {
-            if path.is_dir() {
-                watcher.watch(&path, RecursiveMode::NonRecursive).unwrap();
-                f.load(&path).expect("Couldn't load directory!");
-                let cwd = env::current_dir().unwrap();
-                env::set_current_dir(cwd.join(path)).unwrap();
-                let mut info: frame::Frame = app::widget_from_id("info").unwrap();
-                info.set_label(&format!(
-                    "Directory: {}",
-                    utils::strip_unc_path(&env::current_dir().unwrap())
-                ));
-                f.set_damage(true);
+fn selected_path() -> Option<PathBuf> {
+    let root = STATE.with(|s| s.current_dir.clone());
+    let t: tree::Tree = app::widget_from_id("fbr").unwrap();
+    t.first_selected_item().map(|item| item_path(&root, &item))
+}
+
+pub fn select_for_compare() {
+    if let Some(p) = selected_path().filter(|p| p.is_file()) {
+        *COMPARE.lock().unwrap() = Some(p);
+    }
+}
+
+pub fn compare_with_selected() {
+    let old = COMPARE.lock().unwrap().clone();
+    match (old, selected_path().filter(|p| p.is_file())) {
+        (Some(old), Some(new)) => diffview::compare_files(&old, &new),
+        _ => dialog::message_default("Select a file for compare first"),
+    }
+}
+
+/// Moves or renames `old` to `new`, updating the browser and any open tabs
+fn move_path(old: &Path, new: &Path) {
+    if new.exists() {
+        alert("Couldn't move", format!("{} already exists", new.display()));
+        return;
+    }
+    if let Err(e) = fs::rename(old, new) {
+        alert("Couldn't move", e);
+        return;
+    }
+    for p in [old, new] {
+        if let Some(dir) = p.parent() {
+            refresh(dir);
+        }
+    }
+    let (old, new) = (old.to_path_buf(), new.to_path_buf());
+    STATE.with(move |s| s.rename_path(&old, &new));
+}
+
+pub fn rename() {
+    if let Some(old) = selected_path() {
+        let name = old.file_name().unwrap_or_default().to_string_lossy();
+        if let Some(name) = dialog::input_default("Rename to", &name) {
+            if !name.is_empty() {
+                move_path(&old, &old.with_file_name(name));
+            }
+        }
+    }
+}
+
+fn copy_dir_all(src: &Path, dst: &Path) -> std::io::Result<()> {
+    fs::create_dir(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let to = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &to)?;
+        } else {
+            fs::copy(entry.path(), to)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn duplicate() {
+    if let Some(src) = selected_path() {
+        let stem = src.file_stem().unwrap_or_default().to_string_lossy();
+        let ext = src
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        let mut dst = src.with_file_name(format!("{} copy{}", stem, ext));
+        let mut n = 2;
+        while dst.exists() {
+            dst = src.with_file_name(format!("{} copy {}{}", stem, n, ext));
+            n += 1;
+        }
+        let res = if src.is_dir() {
+            copy_dir_all(&src, &dst)
+        } else {
+            fs::copy(&src, &dst).map(|_| ())
+        };
+        match res {
+            Ok(_) => refresh(dst.parent().unwrap()),
+            Err(e) => alert("Couldn't duplicate", e),
+        }
+    }
+}
+
+pub fn delete() {
+    if let Some(p) = selected_path() {
+        let name = p.file_name().unwrap_or_default().to_string_lossy();
+        if dialog::choice2_default(&format!("Delete {}?", name), "Cancel", "Delete", "") != Some(1)
+        {
+            return;
+        }
+        if trash::delete(&p).is_err() {
+            if dialog::choice2_default(
+                &format!(
+                    "Couldn't move {} to the trash, delete it permanently?",
+                    name
+                ),
+                "Cancel",
+                "Delete",
+                "",
+            ) != Some(1)
+            {
+                return;
+            }
+            let res = if p.is_dir() {
+                fs::remove_dir_all(&p)
             } else {
-                let mut is_image = false;
-                if let Some(ext) = path.extension() {
-                    match ext.to_str().unwrap() {
-                        "jpg" | "gif" | "png" | "bmp" => is_image = true,
-                        _ => (),
-                    }
+                fs::remove_file(&p)
+            };
+            if let Err(e) = res {
+                alert("Couldn't delete", e);
+                return;
+            }
+        }
+        refresh(p.parent().unwrap());
+    }
+}
+
+pub fn copy_path() {
+    copy_selected_path(false);
+}
+
+pub fn copy_relative_path() {
+    copy_selected_path(true);
+}
+
+fn copy_selected_path(relative: bool) {
+    if let Some(p) = selected_path() {
+        let p = if relative {
+            let root = STATE.with(|s| s.current_dir.clone());
+            p.strip_prefix(&root).unwrap_or(&p).to_path_buf()
+        } else {
+            p
+        };
+        app::copy(&utils::strip_unc_path(&p));
+    }
+}
+
+fn icon_for(path: &Path, is_dir: bool) -> image::SvgImage {
+    let svg = if is_dir {
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16">
+        <path d="M1 3h5l2 2h7v9H1z" fill="#c69a66"/></svg>"##
+            .to_string()
+    } else {
+        let color = match path.extension().and_then(|e| e.to_str()).unwrap_or("") {
+            "rs" => "#e06c75",
+            "toml" | "json" | "yaml" | "yml" => "#c69a66",
+            "md" | "txt" => "#61afef",
+            "c" | "h" | "cpp" | "hpp" | "cc" => "#c678dd",
+            "jpg" | "gif" | "png" | "bmp" | "svg" => "#98c379",
+            _ => "#abb2bf",
+        };
+        format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16">
+            <path d="M3 1h7l3 3v11H3z" fill="#abb2bf"/>
+            <rect x="3" y="10" width="10" height="5" fill="{}"/></svg>"##,
+            color
+        )
+    };
+    let mut img = image::SvgImage::from_data(&svg).unwrap();
+    img.scale(16, 16, true, true);
+    img
+}
+
+/// Converts a path relative to the root into an Fl_Tree item path
+fn tree_path(rel: &Path) -> String {
+    rel.iter()
+        .map(|c| c.to_string_lossy().replace('\\', "\\\\"))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The filesystem path an item of the tree rooted at `root` represents
+pub fn item_path(root: &Path, item: &tree::TreeItem) -> PathBuf {
+    let mut parts = vec![];
+    let mut it = Some(item.clone());
+    while let Some(i) = it {
+        if i.is_root() {
+            break;
+        }
+        parts.push(i.label().unwrap_or_default());
+        it = i.parent();
+    }
+    root.join(parts.iter().rev().collect::<PathBuf>())
+}
+
+pub fn find_item(t: &tree::Tree, root: &Path, path: &Path) -> Option<tree::TreeItem> {
+    let rel = path.strip_prefix(root).ok()?;
+    if rel.as_os_str().is_empty() {
+        t.root()
+    } else {
+        t.find_item(&tree_path(rel))
+    }
+}
+
+fn is_loaded(item: &tree::TreeItem) -> bool {
+    item.is_root()
+        || item
+            .child(0)
+            .map(|c| c.label().as_deref() != Some(PLACEHOLDER))
+            .unwrap_or(true)
+}
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    /// gitignored or matching one of the configured exclude globs
+    excluded: bool,
+}
+
+/// Lists `dir` according to the user's config, directories first
+fn sorted_entries(dir: &Path) -> Vec<Entry> {
+    let (config, root) = STATE.with(|s| (s.config.clone(), s.current_dir.clone()));
+    let mut overrides = ignore::overrides::OverrideBuilder::new(&root);
+    for glob in &config.exclude {
+        overrides.add(&format!("!{}", glob)).ok();
+    }
+    let overrides = overrides
+        .build()
+        .unwrap_or_else(|_| ignore::overrides::Override::empty());
+    // the walker honors .gitignore and .ignore files, including those of parent dirs
+    let included: HashSet<_> = ignore::WalkBuilder::new(dir)
+        .max_depth(Some(1))
+        .hidden(false)
+        .overrides(overrides)
+        .build()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.depth() == 1)
+        .map(|e| e.file_name().to_os_string())
+        .collect();
+    let mut v: Vec<_> = fs::read_dir(dir)
+        .map(|rd| {
+            rd.filter_map(|e| e.ok())
+                .map(|e| Entry {
+                    name: e.file_name().to_string_lossy().to_string(),
+                    is_dir: e.path().is_dir(),
+                    excluded: !included.contains(&e.file_name()),
+                })
+                .filter(|e| config.show_hidden || !e.name.starts_with('.'))
+                .filter(|e| config.dim_excluded || !e.excluded)
+                .collect()
+        })
+        .unwrap_or_default();
+    v.sort_by(|a, b| {
+        b.is_dir
+            .cmp(&a.is_dir)
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+    });
+    v
+}
+
+/// Syncs the children of the directory `item` with the filesystem.
+/// Children which still exist are kept, and with them their expansion state.
+pub fn populate(t: &mut tree::Tree, item: &tree::TreeItem, dir: &Path) {
+    let entries = sorted_entries(dir);
+    let git = STATE.with(|s| s.git.clone());
+    let mut i = 0;
+    while i < item.children() {
+        let child = item.child(i).unwrap();
+        let label = child.label().unwrap_or_default();
+        if entries.iter().any(|e| e.name == label) {
+            i += 1;
+        } else {
+            t.remove(&child).ok();
+        }
+    }
+    for (pos, e) in entries.iter().enumerate() {
+        let existing = item
+            .child(pos as i32)
+            .filter(|c| c.label().as_deref() == Some(e.name.as_str()));
+        let child = match existing {
+            Some(c) => Some(c),
+            None => t.insert(item, &e.name, pos as i32).map(|mut child| {
+                child.set_user_icon(Some(icon_for(&dir.join(&e.name), e.is_dir)));
+                if e.is_dir {
+                    t.insert(&child, PLACEHOLDER, 0);
+                    child.close();
                 }
-                if is_image {
-                    let img = image::SharedImage::load(path).unwrap();
-                    let mut win: window::Window = app::widget_from_id("image_dialog").unwrap();
-                    win.resize(win.x(), win.y(), img.w(), img.h());
-                    win.child(0).unwrap().set_image(Some(img));
-                    win.show();
+                child
+            }),
+        };
+        if let Some(mut child) = child {
+            child.set_label_fgcolor(if e.excluded {
+                Color::from_hex(0x5c6370)
+            } else {
+                git.get(&dir.join(&e.name))
+                    .map(|s| s.color())
+                    .unwrap_or(Color::Foreground)
+            });
+        }
+    }
+    t.redraw();
+}
+
+/// Re-filters every expanded directory, e.g. after a config change
+pub fn refresh_all() {
+    fn visit(t: &mut tree::Tree, root: &Path, item: &tree::TreeItem) {
+        if !is_loaded(item) {
+            return;
+        }
+        populate(t, item, &item_path(root, item));
+        for i in 0..item.children() {
+            let child = item.child(i).unwrap();
+            if child.has_children() {
+                visit(t, root, &child);
+            }
+        }
+    }
+    let root = STATE.with(|s| s.current_dir.clone());
+    let mut t: tree::Tree = app::widget_from_id("fbr").unwrap();
+    if let Some(item) = t.root() {
+        visit(&mut t, &root, &item);
+    }
+}
+
+pub fn toggle_hidden() {
+    let flag = STATE.with(|s| {
+        s.config.show_hidden = !s.config.show_hidden;
+        s.config.save();
+        s.config.show_hidden
+    });
+    commands::set_checked("view.hidden_files", flag);
+    refresh_all();
+}
+
+pub fn toggle_dim_excluded() {
+    let flag = STATE.with(|s| {
+        s.config.dim_excluded = !s.config.dim_excluded;
+        s.config.save();
+        s.config.dim_excluded
+    });
+    commands::set_checked("view.dim_excluded", flag);
+    refresh_all();
+}
+
+/// Expands the browser down to `path` and selects it
+pub fn reveal(path: &Path) {
+    let root = STATE.with(|s| s.current_dir.clone());
+    let rel = match path.strip_prefix(&root) {
+        Ok(rel) => rel.to_path_buf(),
+        Err(_) => return,
+    };
+    let mut t: tree::Tree = app::widget_from_id("fbr").unwrap();
+    let mut item = t.root().unwrap();
+    let mut dir = root;
+    for comp in rel.iter() {
+        if !is_loaded(&item) {
+            populate(&mut t, &item, &dir);
+        }
+        if !item.is_root() {
+            item.open();
+            watch(&dir);
+        }
+        dir = dir.join(comp);
+        let name = comp.to_string_lossy();
+        // hidden or excluded entries can't be revealed
+        item = match (0..item.children())
+            .filter_map(|i| item.child(i))
+            .find(|c| c.label().as_deref() == Some(&*name))
+        {
+            Some(c) => c,
+            None => return,
+        };
+    }
+    t.select_only(&item, false).ok();
+    t.show_item_middle(&item);
+    t.redraw();
+}
+
+pub fn reveal_active() {
+    if let Some(f) = STATE.with(|s| s.current_file()) {
+        let fbr: group::Group = app::widget_from_id("fbr_group").unwrap();
+        if fbr.w() <= 1 {
+            cbs::toggle_fbr();
+        }
+        reveal(&f);
+    }
+}
+
+/// Called when the active tab changes
+pub fn follow_active() {
+    if STATE.with(|s| s.config.follow_active_file) {
+        if let Some(f) = STATE.with(|s| s.current_file()) {
+            reveal(&f);
+        }
+    }
+}
+
+pub fn toggle_follow() {
+    let flag = STATE.with(|s| {
+        s.config.follow_active_file = !s.config.follow_active_file;
+        s.config.save();
+        s.config.follow_active_file
+    });
+    commands::set_checked("view.follow_active_file", flag);
+    if flag {
+        follow_active();
+    }
+}
+
+/// The selected directory, or the directory of the selected file, falling back to the root
+pub fn selected_dir() -> PathBuf {
+    let root = STATE.with(|s| s.current_dir.clone());
+    let t: tree::Tree = app::widget_from_id("fbr").unwrap();
+    if let Some(item) = t.first_selected_item() {
+        let p = item_path(&root, &item);
+        if p.is_dir() {
+            return p;
+        } else if let Some(parent) = p.parent() {
+            return parent.to_path_buf();
+        }
+    }
+    root
+}
+
+fn open_file(path: PathBuf) {
+    let mut is_image = false;
+    if let Some(ext) = path.extension() {
+        match ext.to_str().unwrap() {
+            "jpg" | "gif" | "png" | "bmp" => is_image = true,
+            _ => (),
+        }
+    }
+    if is_image {
+        let img = image::SharedImage::load(path).unwrap();
+        let mut win: window::Window = app::widget_from_id("image_dialog").unwrap();
+        win.resize(win.x(), win.y(), img.w(), img.h());
+        win.child(0).unwrap().set_image(Some(img));
+        win.show();
+    } else {
+        STATE.with(move |s| {
+            s.append(Some(path.canonicalize().unwrap()));
+        });
+    }
+}
+
+// how long filesystem events are collected before the browser is updated
+const DEBOUNCE: f64 = 0.2;
+
+/// The watcher and the directories it watches, i.e. the expanded ones
+#[derive(Default)]
+struct Watch {
+    watcher: Option<notify::RecommendedWatcher>,
+    dirs: HashSet<PathBuf>,
+}
+
+/// Changes collected from the watcher thread, awaiting the main thread
+#[derive(Default)]
+struct Pending {
+    dirs: HashSet<PathBuf>,
+    renames: Vec<(PathBuf, PathBuf)>,
+    /// Whether a file was written, which only changes its git status
+    modified: bool,
+}
+
+static WATCH: Lazy<Mutex<Watch>> = Lazy::new(Mutex::default);
+static PENDING: Lazy<Mutex<Pending>> = Lazy::new(Mutex::default);
+
+fn watch(dir: &Path) {
+    let mut w = WATCH.lock().unwrap();
+    let Watch { watcher, dirs } = &mut *w;
+    if let Some(watcher) = watcher.as_mut() {
+        if !dirs.contains(dir) && watcher.watch(dir, RecursiveMode::NonRecursive).is_ok() {
+            dirs.insert(dir.to_path_buf());
+        }
+    }
+}
+
+/// Stops watching `dir` and the directories under it
+fn unwatch(dir: &Path) {
+    let mut w = WATCH.lock().unwrap();
+    let Watch { watcher, dirs } = &mut *w;
+    if let Some(watcher) = watcher.as_mut() {
+        dirs.retain(|d| {
+            if d.starts_with(dir) {
+                watcher.unwatch(d).ok();
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+/// Runs on the watcher's thread, the browser is only touched from the main thread
+fn on_event(event: notify::Event) {
+    let data = match event.kind {
+        EventKind::Create(_)
+        | EventKind::Remove(_)
+        | EventKind::Modify(ModifyKind::Name(_))
+        | EventKind::Modify(ModifyKind::Any) => false,
+        EventKind::Modify(ModifyKind::Data(_)) => true,
+        _ => return,
+    };
+    let mut p = PENDING.lock().unwrap();
+    let first = p.dirs.is_empty() && p.renames.is_empty() && !p.modified;
+    if data {
+        p.modified = true;
+    } else {
+        if let (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) =
+            (&event.kind, &event.paths[..])
+        {
+            p.renames.push((from.clone(), to.clone()));
+        }
+        for path in &event.paths {
+            if let Some(dir) = path.parent() {
+                p.dirs.insert(dir.to_path_buf());
+            }
+        }
+    }
+    if first {
+        app::awake_callback(|| {
+            app::add_timeout3(DEBOUNCE, |_| flush_events());
+        });
+    }
+}
+
+fn flush_events() {
+    let p = std::mem::take(&mut *PENDING.lock().unwrap());
+    for (from, to) in p.renames {
+        // files moved outside of red keep their tabs
+        STATE.with(move |s| s.rename_path(&from, &to));
+    }
+    for dir in p.dirs {
+        refresh(&dir);
+    }
+    git::refresh();
+}
+
+pub fn fbr_cb(t: &mut tree::Tree, root: &Path) {
+    let mut item = match t.callback_item() {
+        Some(item) => item,
+        None => return,
+    };
+    let path = item_path(root, &item);
+    match t.callback_reason() {
+        tree::TreeReason::Opened => {
+            // refresh in case it changed while it was collapsed
+            populate(t, &item, &path);
+            watch(&path);
+        }
+        tree::TreeReason::Closed => unwatch(&path),
+        tree::TreeReason::Selected | tree::TreeReason::Reselected => {
+            if path.is_dir() {
+                if item.is_open() {
+                    item.close();
+                    unwatch(&path);
                 } else {
-                    STATE.with(move |s| {
-                        s.append(Some(path.canonicalize().unwrap()));
-                    });
+                    populate(t, &item, &path);
+                    item.open();
+                    watch(&path);
                 }
+                let mut info: frame::Frame = app::widget_from_id("info").unwrap();
+                info.set_label(&format!("Directory: {}", utils::strip_unc_path(&path)));
+                #[cfg(feature = "term")]
+                terminal::follow_browser(&path);
+                t.redraw();
+            } else if path.exists() {
+                open_file(path);
             }
         }
+        _ => (),
     }
 }
 
@@ -78,44 +632,91 @@ pub struct Fbr {
 
 impl Fbr {
     pub fn new(current_path: &Path) -> Self {
+        let root = current_path.to_path_buf();
         let mut g = group::Group::default().with_id("fbr_group");
-        let mut fbr = browser::FileBrowser::default()
-            .with_type(browser::BrowserType::Hold)
-            .with_id("fbr");
-        fbr.load(current_path)
-            .expect("Failed to load working directory");
+        let mut fbr = tree::Tree::default().with_id("fbr");
+        fbr.set_show_root(false);
+        fbr.set_root_label(&root.file_name().unwrap_or_default().to_string_lossy());
+        fbr.set_select_mode(tree::TreeSelect::Single);
+        fbr.set_trigger(CallbackTrigger::Changed);
+        fbr.set_item_reselect_mode(tree::TreeItemReselectMode::Always);
+        fbr.set_connector_style(tree::TreeConnectorStyle::None);
         fbr.set_color(Color::Background.darker());
+        fbr.set_selection_color(Color::Selection);
+        fbr.set_item_label_fgcolor(Color::Foreground);
+        let root_item = fbr.root().unwrap();
+        populate(&mut fbr, &root_item, &root);
         let mut m = menu::MenuButton::default()
             .with_type(menu::MenuButtonType::Popup3)
             .with_id("pop1");
         init_menu(&mut m);
-        g.end();
-        let mut watcher = notify::recommended_watcher({
+        m.handle({
             let mut fbr = fbr.clone();
-            move |res: Result<Event, notify::Error>| match res {
-                Ok(event) => {
-                    let mut needs_update = false;
-                    match event.kind {
-                        EventKind::Create(_) => {
-                            needs_update = true;
-                        }
-                        EventKind::Remove(_) => {
-                            needs_update = true;
+            move |_, ev| {
+                // target the item under the mouse
+                if ev == Event::Push && app::event_mouse_button() == app::MouseButton::Right {
+                    if let Some(item) = fbr.find_clicked(false) {
+                        fbr.select_only(&item, false).ok();
+                    }
+                }
+                false
+            }
+        });
+        fbr.handle({
+            let root = root.clone();
+            // source path and push position of a potential drag
+            let mut drag: Option<(PathBuf, i32, i32)> = None;
+            let mut dragging = false;
+            move |t, ev| match ev {
+                Event::Push => {
+                    drag = t
+                        .find_clicked(false)
+                        .map(|i| (item_path(&root, &i), app::event_x(), app::event_y()));
+                    dragging = false;
+                    false
+                }
+                Event::Drag => {
+                    if let Some((_, x, y)) = &drag {
+                        if (app::event_x() - x).abs() + (app::event_y() - y).abs() > 5 {
+                            dragging = true;
+                            t.window().unwrap().set_cursor(Cursor::Move);
                         }
-                        _ => (),
                     }
-                    if needs_update {
-                        fbr.load(env::current_dir().unwrap()).unwrap();
+                    dragging
+                }
+                Event::Release => {
+                    let was_dragging = dragging;
+                    dragging = false;
+                    if let (true, Some((src, _, _))) = (was_dragging, drag.take()) {
+                        t.window().unwrap().set_cursor(Cursor::Arrow);
+                        let target = t
+                            .find_clicked(false)
+                            .map(|i| item_path(&root, &i))
+                            .unwrap_or_else(|| root.clone());
+                        let dir = if target.is_dir() {
+                            target
+                        } else {
+                            target.parent().unwrap().to_path_buf()
+                        };
+                        if !dir.starts_with(&src) && Some(dir.as_path()) != src.parent() {
+                            move_path(&src, &dir.join(src.file_name().unwrap()));
+                        }
                     }
+                    was_dragging
                 }
-                Err(e) => eprintln!("{}", e),
+                _ => false,
             }
-        })
-        .unwrap();
-        watcher
-            .watch(current_path, RecursiveMode::NonRecursive)
-            .unwrap();
-        fbr.set_callback(move |f| fbr_cb(f, &mut watcher));
+        });
+        g.end();
+        match notify::recommended_watcher(|res: Result<notify::Event, notify::Error>| match res {
+            Ok(event) => on_event(event),
+            Err(e) => eprintln!("{}", e),
+        }) {
+            Ok(watcher) => WATCH.lock().unwrap().watcher = Some(watcher),
+            Err(e) => eprintln!("{}", e),
+        }
+        watch(&root);
+        fbr.set_callback(move |t| fbr_cb(t, &root));
         g.resize_callback(move |_, x, y, w, h| {
             m.resize(x, y, w, h);
             fbr.resize(x, y, w, h);
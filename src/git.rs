@@ -0,0 +1,160 @@
+use crate::{fbr, gutter::Head, state::STATE};
+use fltk::{app, enums::Color};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+};
+
+/// Ordered by priority, a directory takes the highest status of its entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ignored,
+    Untracked,
+    Added,
+    Modified,
+    Conflicted,
+}
+
+impl Status {
+    pub fn color(self) -> Color {
+        match self {
+            Status::Ignored => Color::from_hex(0x5c6370),
+            Status::Untracked => Color::from_hex(0x56b6c2),
+            Status::Added => Color::from_hex(0x98c379),
+            Status::Modified => Color::from_hex(0xe5c07b),
+            Status::Conflicted => Color::from_hex(0xe06c75),
+        }
+    }
+
+    pub fn badge(self) -> &'static str {
+        match self {
+            Status::Ignored => "!",
+            Status::Untracked => "U",
+            Status::Added => "A",
+            Status::Modified => "M",
+            Status::Conflicted => "C",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StatusMap {
+    entries: HashMap<PathBuf, Status>,
+    ignored_dirs: Vec<PathBuf>,
+}
+
+impl StatusMap {
+    pub fn get(&self, path: &Path) -> Option<Status> {
+        if let Some(s) = self.entries.get(path) {
+            Some(*s)
+        } else if self.ignored_dirs.iter().any(|d| path.starts_with(d)) {
+            Some(Status::Ignored)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn run_git(dir: &Path, args: &[&str]) -> Option<Vec<u8>> {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .ok()?;
+    if out.status.success() {
+        Some(out.stdout)
+    } else {
+        None
+    }
+}
+
+/// The root of the work tree containing `dir`
+pub fn toplevel(dir: &Path) -> Option<PathBuf> {
+    let out = run_git(dir, &["rev-parse", "--show-toplevel"])?;
+    PathBuf::from(String::from_utf8_lossy(&out).trim())
+        .canonicalize()
+        .ok()
+}
+
+pub fn status(root: &Path) -> StatusMap {
+    let mut map = StatusMap::default();
+    let top = match toplevel(root) {
+        Some(top) => top,
+        None => return map,
+    };
+    let out = match run_git(
+        root,
+        &[
+            "status",
+            "--porcelain",
+            "-z",
+            "--untracked-files=all",
+            "--ignored",
+        ],
+    ) {
+        Some(out) => out,
+        None => return map,
+    };
+    let mut fields = out.split(|b| *b == 0);
+    while let Some(f) = fields.next() {
+        if f.len() < 4 {
+            continue;
+        }
+        let (x, y) = (f[0], f[1]);
+        let rel = String::from_utf8_lossy(&f[3..]);
+        let path = top.join(rel.trim_end_matches('/'));
+        if x == b'R' || x == b'C' {
+            // followed by the original path
+            fields.next();
+        }
+        let st = match (x, y) {
+            (b'?', b'?') => Status::Untracked,
+            (b'!', b'!') => {
+                if rel.ends_with('/') {
+                    map.ignored_dirs.push(path);
+                    continue;
+                }
+                Status::Ignored
+            }
+            (b'U', _) | (_, b'U') | (b'A', b'A') | (b'D', b'D') => Status::Conflicted,
+            (b'A', _) => Status::Added,
+            _ => Status::Modified,
+        };
+        if st != Status::Ignored {
+            let mut parent = path.parent();
+            while let Some(dir) = parent.filter(|d| d.starts_with(&top)) {
+                let e = map.entries.entry(dir.to_path_buf()).or_insert(st);
+                *e = (*e).max(st);
+                parent = dir.parent();
+            }
+        }
+        map.entries.insert(path, st);
+    }
+    map
+}
+
+/// Recomputes the status and rereads the open files at HEAD in the background,
+/// then redecorates the browser and tabs
+pub fn refresh() {
+    let (root, open) = STATE.with(|s| (s.current_dir.clone(), s.gutter_files()));
+    thread::spawn(move || {
+        let map = status(&root);
+        let heads: Vec<_> = open
+            .iter()
+            .map(|(path, blame)| Head::read(path, *blame))
+            .collect();
+        app::awake_callback(move || {
+            let map = map.clone();
+            let heads = heads.clone();
+            STATE.with(move |s| {
+                s.git = map.clone();
+                s.update_labels();
+                s.update_gutters(&heads);
+            });
+            fbr::refresh_all();
+        });
+    });
+}
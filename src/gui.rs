@@ -1,13 +1,16 @@
-use crate::{cbs, dialogs, fbr, utils};
+use crate::{
+    cbs, commands, conflict, dialogs, fbr, gutter, output, problems, references, squiggle,
+    state::STATE, tasks, utils,
+};
 use fltk::{enums::*, prelude::*, *};
 use fltk_theme::{SchemeType, WidgetScheme};
 use std::path::{Path, PathBuf};
 
 #[cfg(feature = "term")]
-use fltk_term as term;
+use crate::terminal;
 
 #[cfg(feature = "highlight")]
-use crate::highlight;
+use crate::{highlight, outline};
 
 const WIDTH: i32 = 800;
 const HEIGHT: i32 = 600;
@@ -29,10 +32,19 @@ pub fn init_gui(current_file: &Option<PathBuf>, current_path: &Path) -> app::App
 
     let _find_dialog = dialogs::FindDialog::new();
     let _replace_dialog = dialogs::ReplaceDialog::new();
+    let _goto_dialog = dialogs::GotoDialog::new();
     let _image_dialog = dialogs::ImageDialog::new();
+    let _quick_open = dialogs::QuickOpen::new();
+    let _command_palette = dialogs::CommandPalette::new();
 
     let mut popup = menu::MenuButton::default().with_type(menu::MenuButtonType::Popup3);
-    init_edit_menu(&mut popup, "");
+    init_popup_menu(&mut popup);
+    #[cfg(feature = "term")]
+    let term_popup = {
+        let mut m = menu::MenuButton::default().with_type(menu::MenuButtonType::Popup3);
+        terminal::init_popup_menu(&mut m);
+        m
+    };
 
     let mut w = window::Window::default()
         .with_size(WIDTH, HEIGHT)
@@ -61,16 +73,23 @@ pub fn init_gui(current_file: &Option<PathBuf>, current_path: &Path) -> app::App
     let mut tabs = group::Tabs::default().with_id("tabs");
     tabs.handle(move |t, ev| tabs_handle(t, ev, &mut popup));
     tabs.handle_overflow(group::TabsOverflow::Pulldown);
+    tabs.set_callback(cbs::tabs_cb);
     tabs.end();
     tabs.auto_layout();
+    let mut tab_splitter = frame::Frame::default();
+    tab_splitter.handle(cbs::tab_splitter_cb);
+    col.fixed(&tab_splitter, 4);
+    let mut panel = group::Tabs::default().with_id("panel");
+    panel.handle_overflow(group::TabsOverflow::Pulldown);
+    panel.end();
+    output::init(&mut panel);
+    problems::init(&mut panel);
+    references::init(&mut panel);
+    #[cfg(feature = "highlight")]
+    outline::init(&mut panel);
     #[cfg(feature = "term")]
-    {
-        let mut tab_splitter = frame::Frame::default();
-        tab_splitter.handle(cbs::tab_splitter_cb);
-        col.fixed(&tab_splitter, 4);
-        let term = term::PPTerm::default();
-        col.fixed(&*term, 160);
-    }
+    terminal::init(&mut panel, term_popup);
+    col.fixed(&panel, 160);
     col.end();
     row.end();
     let info = frame::Frame::default()
@@ -107,124 +126,63 @@ pub fn tabs_handle(t: &mut group::Tabs, ev: Event, popup: &mut menu::MenuButton)
     }
 }
 
-pub fn init_edit_menu(m: &mut (impl MenuExt + 'static), header: &str) {
-    m.add(
-        &format!("{}Undo\t", header),
-        Shortcut::Ctrl | 'z',
-        menu::MenuFlag::Normal,
-        cbs::menu_cb,
-    );
-    m.add(
-        &format!("{}Redo\t", header),
-        Shortcut::Ctrl | 'y',
-        menu::MenuFlag::MenuDivider,
-        cbs::menu_cb,
-    );
-    m.add(
-        &format!("{}Cut\t", header),
-        Shortcut::Ctrl | 'x',
-        menu::MenuFlag::Normal,
-        cbs::menu_cb,
-    );
-    m.add(
-        &format!("{}Copy\t", header),
-        Shortcut::Ctrl | 'c',
-        menu::MenuFlag::Normal,
-        cbs::menu_cb,
-    );
-    m.add(
-        &format!("{}Paste\t", header),
-        Shortcut::Ctrl | 'v',
-        menu::MenuFlag::MenuDivider,
-        cbs::menu_cb,
-    );
-    m.add(
-        &format!("{}Find\t", header),
-        Shortcut::Ctrl | 'f',
-        menu::MenuFlag::Normal,
-        cbs::menu_cb,
-    );
-    m.add(
-        &format!("{}Replace\t", header),
-        Shortcut::Ctrl | 'h',
-        menu::MenuFlag::Normal,
-        cbs::menu_cb,
-    );
+pub fn init_popup_menu(m: &mut (impl MenuExt + 'static)) {
+    for cmd in commands::COMMANDS.iter().filter(|c| c.popup) {
+        commands::add_item(m, cmd.id, cmd.label());
+    }
 }
+
 pub fn init_menu(m: &mut (impl MenuExt + 'static), load_dir: bool) {
-    m.add(
-        "&File/New File...\t",
-        Shortcut::Ctrl | 'n',
-        menu::MenuFlag::Normal,
-        cbs::menu_cb,
-    );
-    m.add(
-        "&File/New Dir...\t",
-        Shortcut::Ctrl | Shortcut::Shift | 'n',
-        menu::MenuFlag::Normal,
-        cbs::menu_cb,
-    );
-    m.add(
-        "&File/Open...\t",
-        Shortcut::Ctrl | 'o',
-        menu::MenuFlag::Normal,
-        cbs::menu_cb,
-    );
-    m.add(
-        "&File/Save\t",
-        Shortcut::Ctrl | 's',
-        menu::MenuFlag::Normal,
-        cbs::menu_cb,
-    );
-    m.add(
-        "&File/Save as...\t",
-        Shortcut::Ctrl | Shortcut::Shift | 'w',
-        menu::MenuFlag::Normal,
-        cbs::menu_cb,
-    );
-    m.add(
-        "&File/Save All\t",
-        Shortcut::None,
-        menu::MenuFlag::MenuDivider,
-        cbs::menu_cb,
-    );
-    let idx = m.add(
-        "&File/Quit\t",
-        Shortcut::Ctrl | 'q',
-        menu::MenuFlag::Normal,
-        cbs::menu_cb,
-    );
-    m.at(idx).unwrap().set_label_color(Color::Red);
-    init_edit_menu(m, "&Edit/");
-    let idx = m.add(
-        "&View/File browser\t",
-        Shortcut::None,
-        menu::MenuFlag::Toggle,
-        cbs::menu_cb,
-    );
-    if load_dir {
-        m.at(idx).unwrap().set();
+    for cmd in commands::COMMANDS.iter() {
+        commands::add_item(m, cmd.id, cmd.path);
+    }
+    if let Some(mut item) = m.find_item("&File/Quit\t") {
+        item.set_label_color(Color::Red);
+    }
+    if let Some(mut item) = m.find_item("&View/File browser\t") {
+        if load_dir {
+            item.set();
+        }
     }
+    let (show_hidden, dim_excluded, follow) = STATE.with(|s| {
+        (
+            s.config.show_hidden,
+            s.config.dim_excluded,
+            s.config.follow_active_file,
+        )
+    });
+    if let Some(mut item) = m.find_item("&View/Hidden files\t") {
+        if show_hidden {
+            item.set();
+        }
+    }
+    if let Some(mut item) = m.find_item("&View/Excluded files (dimmed)\t") {
+        if dim_excluded {
+            item.set();
+        }
+    }
+    if let Some(mut item) = m.find_item("&View/Follow active file\t") {
+        if follow {
+            item.set();
+        }
+    }
+    if let Some(mut item) = m.find_item("&View/Panel\t") {
+        item.set();
+    }
+    tasks::init_menu(m);
     #[cfg(feature = "term")]
     {
-        let idx = m.add(
-            "&View/Terminal\t",
-            Shortcut::None,
-            menu::MenuFlag::Toggle,
-            cbs::menu_cb,
-        );
-        m.at(idx).unwrap().set();
+        let follow_browser = STATE.with(|s| s.config.terminal_follows_browser);
+        if let Some(mut item) = m.find_item("&Terminal/Follow File Browser\t") {
+            if follow_browser {
+                item.set();
+            }
+        }
     }
-    m.add(
-        "&Help/About\t",
-        Shortcut::None,
-        menu::MenuFlag::Normal,
-        cbs::menu_cb,
-    );
 }
 
 pub fn init_editor(ed: &mut text::TextEditor) {
-    ed.set_linenumber_width(40);
+    ed.set_linenumber_width(gutter::LINENUMBER_WIDTH);
     ed.set_linenumber_size(12);
     ed.set_linenumber_fgcolor(Color::Yellow.darker());
     ed.set_linenumber_bgcolor(Color::Background);
@@ -237,7 +195,7 @@ pub fn create_ed(
     tabs: &mut group::Tabs,
     id: &str,
     current_path: &Option<PathBuf>,
-) -> text::TextEditor {
+) -> (text::TextEditor, Option<squiggle::Styles>) {
     tabs.begin();
     let mut edrow = group::Flex::default()
         .row()
@@ -264,9 +222,68 @@ pub fn create_ed(
     buf.set_tab_distance(4);
     if let Some(p) = current_path.as_ref() {
         buf.load_file(p).ok();
-        #[cfg(feature = "highlight")]
-        highlight::highlight(p, &mut ed, &mut buf);
     }
+    if !conflict::parse(&buf.text()).is_empty() {
+        conflict::highlight(&mut ed, &mut buf, {
+            let (ed, buf, path) = (ed.clone(), buf.clone(), current_path.clone());
+            move || restore_styles(ed.clone(), buf.clone(), &path)
+        });
+        // the conflict styles leave no room for diagnostics
+        ed.set_buffer(buf);
+        return (ed, None);
+    }
+    let styles = syntax_styles(&mut ed, &mut buf, current_path);
     ed.set_buffer(buf);
-    ed
+    (ed, Some(styles))
+}
+
+/// Highlights `buf` for the language of `path`, leaving room for diagnostics
+fn syntax_styles(
+    ed: &mut text::TextEditor,
+    buf: &mut text::TextBuffer,
+    path: &Option<PathBuf>,
+) -> squiggle::Styles {
+    #[cfg(feature = "highlight")]
+    let styles = path.as_ref().and_then(|p| highlight::highlight(p, ed, buf));
+    #[cfg(not(feature = "highlight"))]
+    let styles = None;
+    styles.unwrap_or_else(|| squiggle::Styles::plain(ed))
+}
+
+/// Brings back the syntax highlighting and diagnostics of `ed` once its conflicts are resolved
+fn restore_styles(mut ed: text::TextEditor, mut buf: text::TextBuffer, path: &Option<PathBuf>) {
+    let edid = ed.as_widget_ptr() as usize;
+    let Some(gutter) = STATE.with(move |s| s.map.get(&edid).map(|b| b.gutter.clone())) else {
+        // the tab was closed
+        return;
+    };
+    let styles = syntax_styles(&mut ed, &mut buf, path);
+    if let Some(gutter) = gutter {
+        gutter.lock().unwrap().set_styles(styles, &buf.text());
+    }
+    ed.redraw();
+}
+
+/// Whether `ev` would edit a read-only editor, leaving navigation, copying and shortcuts alone
+pub fn blocks_edit(ev: Event) -> bool {
+    match ev {
+        Event::KeyDown => {
+            let key = app::event_key();
+            // a consumed key never becomes a shortcut, so only the editing ones are
+            if app::is_event_command() || app::is_event_alt() {
+                return app::is_event_command() && "vxzy".chars().any(|c| key == Key::from_char(c));
+            }
+            matches!(
+                key,
+                Key::BackSpace | Key::Delete | Key::Insert | Key::Enter | Key::KPEnter | Key::Tab
+            ) || app::event_text().chars().any(|c| !c.is_control())
+        }
+        Event::Paste | Event::DndRelease => true,
+        _ => false,
+    }
+}
+
+/// Keeps the user from editing `ed`
+pub fn set_read_only(ed: &mut text::TextEditor) {
+    ed.handle(|_, ev| blocks_edit(ev));
 }
@@ -0,0 +1,408 @@
+use crate::{blame::Blame, diagnostics::Diagnostic, diff, git, squiggle, state::STATE};
+use fltk::{enums::*, prelude::*, *};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+// delay between the last edit and the markers update
+const DELAY: f64 = 0.3;
+const MARKER_WIDTH: i32 = 3;
+const MAX_PREVIEW_LINES: usize = 30;
+pub const LINENUMBER_WIDTH: i32 = 54;
+const ICON_WIDTH: i32 = 12;
+const BLAME_WIDTH: i32 = 260;
+
+/// Markers drawn in the line number area of an editor
+#[derive(Debug, Default)]
+pub struct Gutter {
+    path: Option<PathBuf>,
+    /// The file's lines at HEAD, None if it's not tracked
+    base: Option<Vec<String>>,
+    hunks: Vec<diff::Hunk>,
+    blame: Option<Blame>,
+    diagnostics: Vec<Diagnostic>,
+    /// Where the diagnostics are underlined, None if the editor has no room for them
+    styles: Option<squiggle::Styles>,
+    /// Set once the tab is closed, its buffer is freed by then
+    closed: bool,
+}
+
+/// The HEAD version of an open file, and its blame if shown, read off the main thread
+#[derive(Debug, Clone)]
+pub struct Head {
+    pub path: PathBuf,
+    base: Option<Vec<String>>,
+    /// None if the blame wasn't shown when it was read
+    blame: Option<Option<Blame>>,
+}
+
+impl Head {
+    pub fn read(path: &Path, with_blame: bool) -> Self {
+        Head {
+            path: path.to_path_buf(),
+            base: head_lines(path),
+            blame: with_blame.then(|| Blame::load(path)),
+        }
+    }
+}
+
+fn lines(s: &str) -> Vec<String> {
+    s.split_inclusive('\n').map(String::from).collect()
+}
+
+/// The lines of `path` at HEAD, None if it's not tracked
+fn head_lines(path: &Path) -> Option<Vec<String>> {
+    let name = path.file_name()?.to_str()?;
+    let out = git::run_git(path.parent()?, &["show", &format!("HEAD:./{}", name)])?;
+    Some(lines(&String::from_utf8_lossy(&out)))
+}
+
+impl Gutter {
+    fn new(path: Option<&Path>, styles: Option<squiggle::Styles>) -> Self {
+        Gutter {
+            path: path.map(Path::to_path_buf),
+            styles,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_blame_shown(&self) -> bool {
+        self.blame.is_some()
+    }
+
+    /// Takes the HEAD version read by `Head::read`, e.g. after a commit or a rename
+    pub fn set_head(&mut self, head: Head, text: &str) {
+        self.path = Some(head.path);
+        self.base = head.base;
+        if let Some(blame) = head.blame.filter(|_| self.blame.is_some()) {
+            self.blame = blame;
+        }
+        self.update(text);
+    }
+
+    /// Underlines the diagnostics with `styles` from now on, e.g. once conflicts are resolved
+    pub fn set_styles(&mut self, styles: squiggle::Styles, text: &str) {
+        self.styles = Some(styles);
+        self.underline(text);
+    }
+
+    /// Stops the deferred updates, called when the tab is closed
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    fn update(&mut self, text: &str) {
+        self.hunks = match self.base.as_ref() {
+            Some(base) => diff::diff(base, &lines(text)),
+            None => vec![],
+        };
+    }
+
+    /// Replaces the diagnostics shown for the file
+    pub fn set_diagnostics(&mut self, diagnostics: Vec<Diagnostic>, text: &str) {
+        self.diagnostics = diagnostics;
+        self.underline(text);
+    }
+
+    fn underline(&mut self, text: &str) {
+        if let Some(styles) = self.styles.as_mut() {
+            styles.apply(text, &self.diagnostics);
+        }
+    }
+
+    /// The most severe diagnostic of `line`, 0-based
+    fn diagnostic_at_line(&self, line: usize) -> Option<&Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.line == line + 1)
+            .min_by_key(|d| d.severity)
+    }
+
+    fn hunk_at(&self, line: usize) -> Option<diff::Hunk> {
+        self.hunks.iter().copied().find(|h| {
+            if h.is_deletion() {
+                h.new_start == line
+            } else {
+                (h.new_start..h.new_start + h.new_len).contains(&line)
+            }
+        })
+    }
+
+    /// Shows or hides the blame column, returning whether it's shown
+    pub fn toggle_blame(&mut self) -> bool {
+        self.blame = match self.blame {
+            Some(_) => None,
+            None => self.path.as_deref().and_then(Blame::load),
+        };
+        self.blame.is_some()
+    }
+}
+
+/// The line under the vertical position `y`
+fn line_at(e: &text::TextEditor, y: i32) -> usize {
+    let buf = e.buffer().unwrap();
+    let text_x = e.x() + e.linenumber_width() + 2;
+    let pos = e.xy_to_position(text_x, y, text::PositionType::Cursor);
+    buf.count_lines(0, pos) as usize
+}
+
+fn draw_margin(e: &text::TextEditor, g: &Gutter) {
+    if g.hunks.is_empty() && g.blame.is_none() && g.diagnostics.is_empty() {
+        return;
+    }
+    let buf = e.buffer().unwrap();
+    let text_x = e.x() + e.linenumber_width() + 2;
+    let top = e.xy_to_position(text_x, e.y() + 2, text::PositionType::Cursor);
+    let top_line = line_at(e, e.y() + 2);
+    let bottom_line = line_at(e, e.y() + e.h() - 2) + 1;
+    let (_, y0) = e.position_to_xy(buf.line_start(top));
+    draw::set_font(e.text_font(), e.text_size());
+    let lh = draw::height();
+    let x = e.x() + 2;
+    draw::push_clip(e.x(), e.y(), e.linenumber_width(), e.h());
+    for h in &g.hunks {
+        if h.new_start + h.new_len < top_line || h.new_start > bottom_line {
+            continue;
+        }
+        let y = y0 + (h.new_start as i32 - top_line as i32) * lh;
+        if h.is_deletion() {
+            draw::draw_rect_fill(x, y - 2, MARKER_WIDTH * 2, 4, Color::from_hex(0xe06c75));
+        } else {
+            let color = if h.is_addition() {
+                Color::from_hex(0x98c379)
+            } else {
+                Color::from_hex(0x61afef)
+            };
+            draw::draw_rect_fill(x, y, MARKER_WIDTH, h.new_len as i32 * lh, color);
+        }
+    }
+    // the icons go between the markers and the line numbers
+    let icon_x = e.x() + e.linenumber_width() - LINENUMBER_WIDTH + MARKER_WIDTH * 2 + 2;
+    draw::set_font(Font::Helvetica, e.linenumber_size());
+    for line in top_line..=bottom_line {
+        if let Some(d) = g.diagnostic_at_line(line) {
+            let y = y0 + (line as i32 - top_line as i32) * lh;
+            draw::set_draw_color(d.severity.color());
+            draw::draw_text2(d.severity.icon(), icon_x, y, ICON_WIDTH, lh, Align::Center);
+        }
+    }
+    if let Some(blame) = g.blame.as_ref() {
+        draw::set_font(Font::Courier, e.linenumber_size());
+        draw::set_draw_color(Color::Foreground.darker());
+        let mut last = None;
+        for line in top_line..=bottom_line {
+            // only label the first line of a run from the same commit
+            let commit = blame.commit(line).map(|c| c.hash.as_str());
+            if commit.is_some() && commit != last {
+                let y = y0 + (line as i32 - top_line as i32) * lh;
+                let label = blame.label(line).unwrap_or_default();
+                draw::draw_text2(&label, x + 6, y, BLAME_WIDTH - 8, lh, Align::Left);
+            }
+            last = commit;
+        }
+    }
+    draw::pop_clip();
+}
+
+/// Shows the original lines of the clicked hunk, offering to revert it
+fn hunk_dialog(e: &mut text::TextEditor, g: &Mutex<Gutter>) -> bool {
+    let mut buf = e.buffer().unwrap();
+    let line = line_at(e, app::event_y());
+    // don't hold the lock while the dialog runs its own event loop
+    let (hunk, old) = {
+        let g = g.lock().unwrap();
+        match (g.hunk_at(line), g.base.as_ref()) {
+            (Some(h), Some(base)) => (h, base[h.old_start..h.old_start + h.old_len].concat()),
+            _ => return false,
+        }
+    };
+    let mut preview: Vec<_> = old.lines().take(MAX_PREVIEW_LINES).collect();
+    if hunk.old_len > MAX_PREVIEW_LINES {
+        preview.push("...");
+    }
+    let msg = if hunk.is_addition() {
+        "Added lines".to_string()
+    } else {
+        format!("Original:\n{}", preview.join("\n"))
+    };
+    if dialog::choice2_default(&msg, "Close", "Revert hunk", "") == Some(1) {
+        let start = buf.skip_lines(0, hunk.new_start as i32);
+        let end = buf.skip_lines(0, (hunk.new_start + hunk.new_len) as i32);
+        buf.replace(start, end, &old);
+        e.do_callback();
+    }
+    true
+}
+
+/// Shows the full message of the commit blamed for the clicked line
+fn commit_dialog(e: &text::TextEditor, g: &Mutex<Gutter>) -> bool {
+    let line = line_at(e, app::event_y());
+    let (msg, diff) = {
+        let g = g.lock().unwrap();
+        let Some(blame) = g.blame.as_ref() else {
+            return false;
+        };
+        let Some(c) = blame.commit(line).filter(|c| c.is_committed()) else {
+            return true;
+        };
+        let msg = format!(
+            "{} by {}, {}\n\n{}",
+            c.short_hash(),
+            c.author,
+            crate::blame::relative_date(c.time),
+            blame.message(c)
+        );
+        (msg, blame.show(c).map(|d| (c.short_hash().to_string(), d)))
+    };
+    if dialog::choice2_default(&msg, "Close", "Show diff", "") == Some(1) {
+        if let Some((hash, diff)) = diff {
+            STATE.with(move |s| s.append_read_only(&hash, &diff));
+        }
+    }
+    true
+}
+
+/// Shows the summary of the commit blamed for the hovered line,
+/// or the message of the hovered diagnostic
+fn hover_tooltip(e: &mut text::TextEditor, g: &Mutex<Gutter>, in_blame: bool, in_margin: bool) {
+    let line = line_at(e, app::event_y());
+    let g = g.lock().unwrap();
+    let tip = if in_blame {
+        g.blame
+            .as_ref()
+            .and_then(|b| b.commit(line))
+            .filter(|c| c.is_committed())
+            .map(|c| format!("{} {}", c.short_hash(), c.summary))
+    } else if in_margin {
+        g.diagnostic_at_line(line).map(|d| d.message.clone())
+    } else {
+        let buf = e.buffer().unwrap();
+        let pos = e.xy_to_position(
+            app::event_x(),
+            app::event_y(),
+            text::PositionType::Character,
+        );
+        let line_start = buf.line_start(pos);
+        let col = buf.count_displayed_characters(line_start, pos) as usize + 1;
+        g.diagnostics
+            .iter()
+            .find(|d| {
+                let start = (d.line, d.col);
+                let end = (d.end_line, d.end_col).max((d.line, d.col + 1));
+                (start..end).contains(&(line + 1, col))
+            })
+            .map(|d| d.message.clone())
+    }
+    .unwrap_or_default();
+    drop(g);
+    if e.tooltip().unwrap_or_default() != tip {
+        e.set_tooltip(&tip);
+    }
+}
+
+/// Widens the line number area of `ed` to fit the blame column
+pub fn set_blame_visible(ed: &mut text::TextEditor, show: bool) {
+    ed.set_linenumber_width(if show {
+        LINENUMBER_WIDTH + BLAME_WIDTH
+    } else {
+        LINENUMBER_WIDTH
+    });
+    ed.redraw();
+}
+
+/// Draws git change markers next to the line numbers of `ed` and keeps them updated
+pub fn attach(
+    ed: &mut text::TextEditor,
+    path: Option<&Path>,
+    styles: Option<squiggle::Styles>,
+) -> Arc<Mutex<Gutter>> {
+    let mut buf = ed.buffer().unwrap();
+    let g = Arc::new(Mutex::new(Gutter::new(path, styles)));
+    // the markers show up once `git show` is done, which can take a while
+    if let Some(path) = path.map(Path::to_path_buf) {
+        let (g, buf, mut ed) = (g.clone(), buf.clone(), ed.clone());
+        thread::spawn(move || {
+            let mut base = Some(head_lines(&path));
+            app::awake_callback(move || {
+                let Some(base) = base.take() else {
+                    return;
+                };
+                let mut g = g.lock().unwrap();
+                if g.closed || ed.was_deleted() {
+                    return;
+                }
+                g.base = base;
+                g.update(&buf.text());
+                ed.redraw();
+            });
+        });
+    }
+    buf.add_modify_callback({
+        let g = g.clone();
+        let buf = buf.clone();
+        let ed = ed.clone();
+        let mut handle: Option<app::TimeoutHandle> = None;
+        move |_, _, _, _, _| {
+            if let Some(h) = handle.take() {
+                app::remove_timeout3(h);
+            }
+            // once the highlighter has rewritten the styles
+            let g = g.clone();
+            let buf = buf.clone();
+            let mut ed = ed.clone();
+            handle = Some(app::add_timeout3(0.0, move |_| {
+                let mut g = g.lock().unwrap();
+                if g.closed || ed.was_deleted() {
+                    return;
+                }
+                g.underline(&buf.text());
+                ed.redraw();
+            }));
+        }
+    });
+    buf.add_modify_callback({
+        let g = g.clone();
+        let buf = buf.clone();
+        let ed = ed.clone();
+        let mut handle: Option<app::TimeoutHandle> = None;
+        move |_, _, _, _, _| {
+            if let Some(h) = handle.take() {
+                app::remove_timeout3(h);
+            }
+            let g = g.clone();
+            let buf = buf.clone();
+            let mut ed = ed.clone();
+            handle = Some(app::add_timeout3(DELAY, move |_| {
+                let mut g = g.lock().unwrap();
+                if g.closed || ed.was_deleted() {
+                    return;
+                }
+                g.update(&buf.text());
+                ed.redraw();
+            }));
+        }
+    });
+    ed.draw({
+        let g = g.clone();
+        move |e| draw_margin(e, &g.lock().unwrap())
+    });
+    ed.handle({
+        let g = g.clone();
+        move |e, ev| {
+            let in_margin = app::event_x() < e.x() + e.linenumber_width();
+            let in_blame = app::event_x() < e.x() + e.linenumber_width() - LINENUMBER_WIDTH;
+            match ev {
+                Event::Push if in_blame => commit_dialog(e, &g),
+                Event::Push if in_margin => hunk_dialog(e, &g),
+                Event::Move => {
+                    hover_tooltip(e, &g, in_blame, in_margin);
+                    false
+                }
+                _ => false,
+            }
+        }
+    });
+    g
+}
@@ -21,3 +21,9 @@ pub fn lang_data() -> HighlightData {
     config.configure(&names);
     HighlightData::new(styles, config, None)
 }
+
+/// Definitions listed in the outline, tags style
+pub const OUTLINE_QUERY: &str = r#"
+(atx_heading (inline) @name) @definition.heading
+(setext_heading (paragraph (inline) @name)) @definition.heading
+"#;
@@ -1,3 +1,4 @@
+use crate::squiggle;
 use fltk::{
     app,
     enums::{Color, Font},
@@ -65,36 +66,65 @@ fn get_highlight(p: &Path) -> Option<HighlightData> {
     }
 }
 
-pub fn highlight(p: &Path, ed: &mut TextEditor, buf: &mut TextBuffer) {
-    if let Some(HighlightData {
+/// The keywords of the language of `p`, i.e. the word tokens of its grammar
+pub fn keywords(p: &Path) -> Vec<String> {
+    let language = match p.extension().and_then(|e| e.to_str()) {
+        Some("rs") => tree_sitter_rust::language(),
+        Some("toml") => tree_sitter_toml::language(),
+        _ => return vec![],
+    };
+    let mut v: Vec<String> = (0..language.node_kind_count() as u16)
+        .filter(|&id| !language.node_kind_is_named(id) && language.node_kind_is_visible(id))
+        .filter_map(|id| language.node_kind_for_id(id))
+        .filter(|k| k.len() > 1 && k.chars().all(|c| c.is_ascii_alphabetic() || c == '_'))
+        .map(String::from)
+        .collect();
+    v.sort();
+    v.dedup();
+    v
+}
+
+/// The grammar of `p` and its query for the outline's definitions
+pub fn outline_query(p: &Path) -> Option<(tree_sitter::Language, &'static str)> {
+    match p.extension()?.to_str()? {
+        "rs" => Some((tree_sitter_rust::language(), rust::OUTLINE_QUERY)),
+        "toml" => Some((tree_sitter_toml::language(), toml::OUTLINE_QUERY)),
+        "md" => Some((tree_sitter_md::language(), md::OUTLINE_QUERY)),
+        _ => None,
+    }
+}
+
+/// Highlights `buf` according to the language of `p`, returning the style buffer
+/// if the language is supported
+pub fn highlight(p: &Path, ed: &mut TextEditor, buf: &mut TextBuffer) -> Option<squiggle::Styles> {
+    let HighlightData {
         styles,
         config,
         exeption_fn,
-    }) = get_highlight(p)
-    {
-        let mut highlighter = Highlighter::new();
-        let mut sbuf = TextBuffer::default();
-        ed.set_highlight_data(sbuf.clone(), styles);
-        apply(
-            &mut highlighter,
-            &config,
-            &buf.text(),
-            &mut sbuf,
-            &exeption_fn,
-        );
-        buf.add_modify_callback({
-            let buf = buf.clone();
-            move |_, _, _, _, _| {
-                apply(
-                    &mut highlighter,
-                    &config,
-                    &buf.text(),
-                    &mut sbuf,
-                    &exeption_fn,
-                );
-            }
-        });
-    }
+    } = get_highlight(p)?;
+    let mut highlighter = Highlighter::new();
+    let mut sbuf = TextBuffer::default();
+    let squiggles = squiggle::Styles::install(ed, sbuf.clone(), styles);
+    apply(
+        &mut highlighter,
+        &config,
+        &buf.text(),
+        &mut sbuf,
+        &exeption_fn,
+    );
+    buf.add_modify_callback({
+        let buf = buf.clone();
+        move |_, _, _, _, _| {
+            apply(
+                &mut highlighter,
+                &config,
+                &buf.text(),
+                &mut sbuf,
+                &exeption_fn,
+            );
+        }
+    });
+    Some(squiggles)
 }
 
 fn apply(
@@ -22,3 +22,9 @@ pub fn lang_data() -> HighlightData {
     config.configure(&names);
     HighlightData::new(styles, config, None)
 }
+
+/// Definitions listed in the outline, tags style
+pub const OUTLINE_QUERY: &str = r#"
+(table [(bare_key) (dotted_key) (quoted_key)] @name) @definition.table
+(table_array_element [(bare_key) (dotted_key) (quoted_key)] @name) @definition.table_array
+"#;
@@ -0,0 +1,155 @@
+use crate::utils;
+use std::{fs, path::PathBuf};
+
+const MAX_ENTRIES: usize = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryKind {
+    Search,
+    Replace,
+}
+
+impl HistoryKind {
+    fn file_name(&self) -> &'static str {
+        match self {
+            HistoryKind::Search => "search.history",
+            HistoryKind::Replace => "replace.history",
+        }
+    }
+}
+
+/// A most-recent-first list of entries persisted in the user's config dir.
+pub struct History {
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+    pos: Option<usize>,
+}
+
+impl History {
+    pub fn load(kind: HistoryKind) -> Self {
+        let path = utils::config_dir().map(|d| d.join(kind.file_name()));
+        let entries = path
+            .as_ref()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .map(|s| {
+                s.lines()
+                    .filter(|l| !l.is_empty())
+                    .take(MAX_ENTRIES)
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            path,
+            entries,
+            pos: None,
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn push(&mut self, entry: &str) {
+        self.pos = None;
+        if entry.is_empty() || entry.contains('\n') {
+            return;
+        }
+        if self.entries.first().map(|e| e == entry).unwrap_or(false) {
+            return;
+        }
+        self.entries.retain(|e| e != entry);
+        self.entries.insert(0, entry.to_string());
+        self.entries.truncate(MAX_ENTRIES);
+        self.save();
+    }
+
+    /// Steps back in time, returns None when there's nothing older.
+    pub fn older(&mut self) -> Option<String> {
+        let next = self.pos.map_or(0, |p| p + 1);
+        let entry = self.entries.get(next)?.clone();
+        self.pos = Some(next);
+        Some(entry)
+    }
+
+    /// Steps forward in time, returns an empty entry once past the newest one.
+    pub fn newer(&mut self) -> Option<String> {
+        match self.pos {
+            Some(0) => {
+                self.pos = None;
+                Some(String::new())
+            }
+            Some(p) => {
+                self.pos = Some(p - 1);
+                self.entries.get(p - 1).cloned()
+            }
+            None => None,
+        }
+    }
+
+    fn save(&self) {
+        if let Some(p) = self.path.as_ref() {
+            if let Some(parent) = p.parent() {
+                fs::create_dir_all(parent).ok();
+            }
+            fs::write(p, self.entries.join("\n")).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(entries: &[&str]) -> History {
+        History {
+            path: None,
+            entries: entries.iter().map(|e| e.to_string()).collect(),
+            pos: None,
+        }
+    }
+
+    #[test]
+    fn push_moves_entries_to_the_front() {
+        let mut h = history(&["b", "a"]);
+        h.push("a");
+        h.push("c");
+        h.push("c");
+        h.push("");
+        h.push("multi\nline");
+        assert_eq!(h.entries(), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn push_keeps_the_newest_entries() {
+        let mut h = history(&[]);
+        for i in 0..MAX_ENTRIES + 5 {
+            h.push(&i.to_string());
+        }
+        assert_eq!(h.entries().len(), MAX_ENTRIES);
+        assert_eq!(h.entries()[0], (MAX_ENTRIES + 4).to_string());
+    }
+
+    #[test]
+    fn older_and_newer_walk_the_entries() {
+        let mut h = history(&["c", "b", "a"]);
+        assert_eq!(h.newer(), None);
+        assert_eq!(h.older().as_deref(), Some("c"));
+        assert_eq!(h.older().as_deref(), Some("b"));
+        assert_eq!(h.older().as_deref(), Some("a"));
+        assert_eq!(h.older(), None);
+        assert_eq!(h.newer().as_deref(), Some("b"));
+        assert_eq!(h.newer().as_deref(), Some("c"));
+        assert_eq!(h.newer().as_deref(), Some(""));
+        assert_eq!(h.newer(), None);
+    }
+
+    #[test]
+    fn push_resets_the_position() {
+        let mut h = history(&["b", "a"]);
+        h.older();
+        h.older();
+        h.push("c");
+        assert_eq!(h.older().as_deref(), Some("c"));
+    }
+}
@@ -0,0 +1,653 @@
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+// how long a server gets to answer `shutdown`, and then to exit
+const EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An error returned by the server for a request
+#[derive(Debug, Clone)]
+pub struct ResponseError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl std::fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+type Callback = Box<dyn FnOnce(Result<Value, ResponseError>) + Send>;
+
+/// How the server wants document changes to be sent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncKind {
+    None,
+    Full,
+    Incremental,
+}
+
+#[derive(Default)]
+struct Shared {
+    pending: HashMap<i64, Callback>,
+    /// Messages sent before the server answered `initialize`, None once it did
+    queue: Option<Vec<Value>>,
+    capabilities: Value,
+}
+
+/// A connection to a language server, independent of how it's run,
+/// which calls back from its reader thread
+pub struct Client {
+    sender: mpsc::Sender<Value>,
+    next_id: AtomicI64,
+    shared: Arc<Mutex<Shared>>,
+    child: Mutex<Option<Child>>,
+}
+
+/// Reads one `Content-Length` framed message, None at the end of the stream
+pub fn read_message(r: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            if k.eq_ignore_ascii_case("content-length") {
+                len = v.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let len = len.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no Content-Length"))?;
+    let mut body = vec![0; len];
+    r.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(w: &mut impl Write, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    w.flush()
+}
+
+fn message(method: &str, params: Value) -> Value {
+    let mut msg = json!({ "jsonrpc": "2.0", "method": method });
+    if !params.is_null() {
+        msg["params"] = params;
+    }
+    msg
+}
+
+/// The answer to a request the server made, which we mostly don't support
+fn reply(method: &str, params: &Value) -> Value {
+    match method {
+        "workspace/configuration" => {
+            let items = params["items"].as_array().map_or(0, Vec::len);
+            Value::Array(vec![Value::Null; items])
+        }
+        _ => Value::Null,
+    }
+}
+
+impl Client {
+    /// Talks to a server through `reader` and `writer`, starting with the `initialize`
+    /// handshake for `root`. `on_notification` gets the server's notifications
+    pub fn new(
+        reader: impl Read + Send + 'static,
+        mut writer: impl Write + Send + 'static,
+        root: &Path,
+        on_notification: impl Fn(&str, Value) + Send + 'static,
+    ) -> Arc<Self> {
+        let (sender, receiver) = mpsc::channel::<Value>();
+        thread::spawn(move || {
+            for msg in receiver {
+                if write_message(&mut writer, &msg).is_err() {
+                    break;
+                }
+            }
+        });
+        let shared = Arc::new(Mutex::new(Shared {
+            queue: Some(vec![]),
+            ..Default::default()
+        }));
+        thread::spawn({
+            let shared = shared.clone();
+            let sender = sender.clone();
+            move || {
+                let mut reader = BufReader::new(reader);
+                while let Ok(Some(msg)) = read_message(&mut reader) {
+                    let method = msg["method"].as_str();
+                    match (method, msg.get("id")) {
+                        (Some(method), Some(id)) => {
+                            let result = reply(method, &msg["params"]);
+                            let res = json!({ "jsonrpc": "2.0", "id": id, "result": result });
+                            sender.send(res).ok();
+                        }
+                        (Some(method), None) => on_notification(method, msg["params"].clone()),
+                        (None, Some(id)) => {
+                            let cb = id
+                                .as_i64()
+                                .and_then(|id| shared.lock().unwrap().pending.remove(&id));
+                            if let Some(cb) = cb {
+                                cb(match msg.get("error") {
+                                    Some(e) => Err(ResponseError {
+                                        code: e["code"].as_i64().unwrap_or_default(),
+                                        message: e["message"].as_str().unwrap_or_default().into(),
+                                    }),
+                                    None => Ok(msg["result"].clone()),
+                                });
+                            }
+                        }
+                        (None, None) => (),
+                    }
+                }
+                // nothing answers once the server is gone
+                shared.lock().unwrap().pending.clear();
+            }
+        });
+        let client = Arc::new(Self {
+            sender,
+            next_id: AtomicI64::new(0),
+            shared,
+            child: Mutex::new(None),
+        });
+        client.initialize(root);
+        client
+    }
+
+    /// Runs `command` in `root` and talks to it through its stdio
+    pub fn spawn(
+        command: &str,
+        args: &[String],
+        root: &Path,
+        on_notification: impl Fn(&str, Value) + Send + 'static,
+    ) -> io::Result<Arc<Self>> {
+        let mut child = Command::new(command)
+            .args(args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let client = Self::new(stdout, stdin, root, on_notification);
+        *client.child.lock().unwrap() = Some(child);
+        Ok(client)
+    }
+
+    fn initialize(&self, root: &Path) {
+        let uri = path_to_uri(root);
+        let name = root.file_name().and_then(|n| n.to_str()).unwrap_or("root");
+        let params = json!({
+            "processId": std::process::id(),
+            "rootUri": uri,
+            "workspaceFolders": [{ "uri": uri, "name": name }],
+            "capabilities": {
+                "textDocument": {
+                    "synchronization": { "didSave": true },
+                    "publishDiagnostics": {},
+                    "hover": { "contentFormat": ["plaintext", "markdown"] },
+                    "definition": { "linkSupport": true },
+                    "references": {},
+                    "completion": {
+                        "completionItem": {
+                            "snippetSupport": false,
+                            "documentationFormat": ["plaintext", "markdown"],
+                        },
+                    },
+                },
+                "general": { "positionEncodings": ["utf-16"] },
+            },
+        });
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let shared = self.shared.clone();
+        let sender = self.sender.clone();
+        let cb: Callback = Box::new(move |res| {
+            let mut shared = shared.lock().unwrap();
+            let queue = shared.queue.take().unwrap_or_default();
+            match res {
+                Ok(result) => {
+                    shared.capabilities = result["capabilities"].clone();
+                    sender.send(message("initialized", json!({}))).ok();
+                    for msg in queue {
+                        sender.send(msg).ok();
+                    }
+                }
+                Err(e) => eprintln!("Language server failed to initialize: {}", e),
+            }
+        });
+        self.shared.lock().unwrap().pending.insert(id, cb);
+        let mut msg = message("initialize", params);
+        msg["id"] = id.into();
+        self.sender.send(msg).ok();
+    }
+
+    fn send(&self, msg: Value) {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.queue.as_mut() {
+            Some(queue) => queue.push(msg),
+            None => {
+                self.sender.send(msg).ok();
+            }
+        }
+    }
+
+    pub fn notify(&self, method: &str, params: Value) {
+        self.send(message(method, params));
+    }
+
+    /// Sends a request, `cb` getting its result from the reader thread
+    pub fn request(
+        &self,
+        method: &str,
+        params: Value,
+        cb: impl FnOnce(Result<Value, ResponseError>) + Send + 'static,
+    ) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.shared.lock().unwrap().pending.insert(id, Box::new(cb));
+        let mut msg = message(method, params);
+        msg["id"] = id.into();
+        self.send(msg);
+    }
+
+    /// The capabilities the server announced, null until it's initialized
+    pub fn capabilities(&self) -> Value {
+        self.shared.lock().unwrap().capabilities.clone()
+    }
+
+    /// Full until the server says otherwise, since full changes are always understood
+    pub fn sync_kind(&self) -> SyncKind {
+        let caps = self.capabilities();
+        let sync = &caps["textDocumentSync"];
+        match sync.get("change").unwrap_or(sync).as_u64() {
+            Some(0) => SyncKind::None,
+            Some(2) => SyncKind::Incremental,
+            _ => SyncKind::Full,
+        }
+    }
+
+    /// Asks the server to exit, killing it if it doesn't in time
+    pub fn shutdown(&self) {
+        // `exit` before the answer to `shutdown` counts as a crash
+        let (tx, rx) = mpsc::channel();
+        self.request("shutdown", Value::Null, move |_| {
+            tx.send(()).ok();
+        });
+        rx.recv_timeout(EXIT_TIMEOUT).ok();
+        self.notify("exit", Value::Null);
+        if let Some(mut child) = self.child.lock().unwrap().take() {
+            let step = Duration::from_millis(50);
+            let mut waited = Duration::ZERO;
+            while waited < EXIT_TIMEOUT {
+                if let Ok(Some(_)) = child.try_wait() {
+                    return;
+                }
+                thread::sleep(step);
+                waited += step;
+            }
+            child.kill().ok();
+        }
+    }
+}
+
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"-._~/".contains(&b)
+}
+
+pub fn path_to_uri(path: &Path) -> String {
+    let s = path.to_string_lossy().replace('\\', "/");
+    // windows paths get an extra slash before the drive letter
+    let mut uri = String::from(if s.starts_with('/') {
+        "file://"
+    } else {
+        "file:///"
+    });
+    for (i, b) in s.bytes().enumerate() {
+        if is_unreserved(b) || (b == b':' && i == 1) {
+            uri.push(b as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", b));
+        }
+    }
+    uri
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let s = uri.strip_prefix("file://")?;
+    let mut bytes = vec![];
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    let s = String::from_utf8(bytes).ok()?;
+    // `/C:/foo` on windows
+    let s = match s.as_bytes() {
+        [b'/', _, b':', ..] if cfg!(target_os = "windows") => &s[1..],
+        _ => &s[..],
+    };
+    Some(PathBuf::from(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// The test's end of a connection to a client, acting as the server
+    struct FakeServer {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl FakeServer {
+        fn read(&mut self) -> Value {
+            read_message(&mut self.reader).unwrap().unwrap()
+        }
+
+        fn write(&mut self, msg: Value) {
+            write_message(&mut self.writer, &msg).unwrap();
+        }
+
+        /// Whether the client sent nothing for a while
+        fn is_quiet(&mut self) -> bool {
+            let stream = self.reader.get_ref();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            let quiet = read_message(&mut self.reader).is_err();
+            self.reader.get_ref().set_read_timeout(None).unwrap();
+            quiet
+        }
+
+        /// Answers `initialize` with `capabilities`, returning the request
+        fn initialize(&mut self, capabilities: Value) -> Value {
+            let req = self.read();
+            self.write(json!({ "jsonrpc": "2.0", "id": req["id"], "result": { "capabilities": capabilities } }));
+            assert_eq!(self.read()["method"], "initialized");
+            req
+        }
+    }
+
+    /// A server for `sh`, answering `initialize`, echoing the next request as its result
+    /// and exiting once it got `exit` after `shutdown`
+    #[cfg(unix)]
+    const FAKE_SERVER: &str = r#"
+    LC_ALL=C
+    read_msg() {
+        len=0
+        while IFS= read -r line; do
+            line=$(printf %s "$line" | tr -d '\r')
+            [ -z "$line" ] && break
+            case $line in Content-Length:*) len=${line#*: } ;; esac
+        done
+        dd bs=1 count="$len" 2>/dev/null
+    }
+    send() { printf 'Content-Length: %s\r\n\r\n%s' "${#1}" "$1"; }
+    # more logs than a pipe holds, which would block if stderr were piped and never read
+    i=0
+    while [ $i -lt 5000 ]; do
+        echo "log line $i" >&2
+        i=$((i + 1))
+    done
+    read_msg >/dev/null
+    send '{"jsonrpc":"2.0","id":0,"result":{"capabilities":{"hoverProvider":true}}}'
+    read_msg >/dev/null
+    req=$(read_msg)
+    send "{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":$req}"
+    read_msg >/dev/null
+    send '{"jsonrpc":"2.0","id":2,"result":null}'
+    [ "$(read_msg)" = '{"jsonrpc":"2.0","method":"exit"}' ] && exit 0
+    exit 1
+"#;
+
+    fn connect(
+        on_notification: impl Fn(&str, Value) + Send + 'static,
+    ) -> (Arc<Client>, FakeServer) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client_end = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_end, _) = listener.accept().unwrap();
+        let client = Client::new(
+            client_end.try_clone().unwrap(),
+            client_end,
+            Path::new("/tmp/project"),
+            on_notification,
+        );
+        let server = FakeServer {
+            reader: BufReader::new(server_end.try_clone().unwrap()),
+            writer: server_end,
+        };
+        (client, server)
+    }
+
+    #[test]
+    fn frames_messages() {
+        let mut out = vec![];
+        write_message(&mut out, &json!({ "a": "é" })).unwrap();
+        assert_eq!(out, "Content-Length: 10\r\n\r\n{\"a\":\"é\"}".as_bytes());
+        let mut r = io::Cursor::new(out);
+        assert_eq!(read_message(&mut r).unwrap(), Some(json!({ "a": "é" })));
+        assert_eq!(read_message(&mut r).unwrap(), None);
+        let mut r = io::Cursor::new("Content-Type: x\r\n\r\n{}");
+        assert!(read_message(&mut r).is_err());
+    }
+
+    #[test]
+    fn initializes() {
+        let (client, mut server) = connect(|_, _| ());
+        assert_eq!(client.sync_kind(), SyncKind::Full);
+        let req = server.initialize(json!({ "textDocumentSync": { "change": 2 } }));
+        assert_eq!(req["method"], "initialize");
+        assert_eq!(req["params"]["rootUri"], "file:///tmp/project");
+        assert_eq!(req["params"]["workspaceFolders"][0]["name"], "project");
+        assert_eq!(client.sync_kind(), SyncKind::Incremental);
+    }
+
+    #[test]
+    fn queues_until_initialized() {
+        let (client, mut server) = connect(|_, _| ());
+        client.notify(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "version": 0 } }),
+        );
+        client.notify(
+            "textDocument/didChange",
+            json!({ "textDocument": { "version": 1 } }),
+        );
+        assert_eq!(server.read()["method"], "initialize");
+        assert!(server.is_quiet());
+        server.write(json!({ "jsonrpc": "2.0", "id": 0, "result": { "capabilities": {} } }));
+        assert_eq!(server.read()["method"], "initialized");
+        assert_eq!(server.read()["method"], "textDocument/didOpen");
+        let change = server.read();
+        assert_eq!(change["method"], "textDocument/didChange");
+        assert_eq!(change["params"]["textDocument"]["version"], 1);
+        // sent right away from now on
+        client.notify("textDocument/didSave", Value::Null);
+        let save = server.read();
+        assert_eq!(save["method"], "textDocument/didSave");
+        assert!(save.get("params").is_none());
+    }
+
+    #[test]
+    fn dispatches_notifications() {
+        let (tx, rx) = mpsc::channel();
+        let (_client, mut server) = connect(move |method, params| {
+            tx.send((method.to_string(), params)).unwrap();
+        });
+        server.initialize(json!({}));
+        let params = json!({
+            "uri": "file:///tmp/project/main.rs",
+            "diagnostics": [{ "range": {}, "message": "oops" }],
+        });
+        server.write(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": params,
+        }));
+        let (method, got) = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(method, "textDocument/publishDiagnostics");
+        assert_eq!(got, params);
+    }
+
+    #[test]
+    fn answers_server_requests() {
+        let (_client, mut server) = connect(|_, _| ());
+        server.initialize(json!({}));
+        server.write(json!({
+            "jsonrpc": "2.0",
+            "id": "cfg",
+            "method": "workspace/configuration",
+            "params": { "items": [{}, {}] },
+        }));
+        let res = server.read();
+        assert_eq!(res["id"], "cfg");
+        assert_eq!(res["result"], json!([null, null]));
+    }
+
+    #[test]
+    fn round_trips_requests() {
+        let (client, mut server) = connect(|_, _| ());
+        server.initialize(json!({ "definitionProvider": true }));
+        let (tx, rx) = mpsc::channel();
+        let position = json!({ "line": 2, "character": 4 });
+        client.request(
+            "textDocument/definition",
+            json!({ "position": position }),
+            {
+                let tx = tx.clone();
+                move |res| tx.send(res).unwrap()
+            },
+        );
+        let req = server.read();
+        assert_eq!(req["method"], "textDocument/definition");
+        assert_eq!(req["params"]["position"], position);
+        let location = json!({
+            "uri": "file:///tmp/project/lib.rs",
+            "range": { "start": position, "end": position },
+        });
+        server.write(json!({ "jsonrpc": "2.0", "id": req["id"], "result": [location] }));
+        let res = rx.recv_timeout(TIMEOUT).unwrap().unwrap();
+        assert_eq!(res, json!([location]));
+
+        client.request("textDocument/definition", Value::Null, move |res| {
+            tx.send(res).unwrap()
+        });
+        let req = server.read();
+        server.write(json!({
+            "jsonrpc": "2.0",
+            "id": req["id"],
+            "error": { "code": -32601, "message": "unsupported" },
+        }));
+        let err = rx.recv_timeout(TIMEOUT).unwrap().unwrap_err();
+        assert_eq!((err.code, err.message.as_str()), (-32601, "unsupported"));
+    }
+
+    #[test]
+    fn waits_for_shutdown_before_exit() {
+        let (client, mut server) = connect(|_, _| ());
+        server.initialize(json!({}));
+        let done = thread::spawn(move || client.shutdown());
+        let req = server.read();
+        assert_eq!(req["method"], "shutdown");
+        assert!(server.is_quiet());
+        server.write(json!({ "jsonrpc": "2.0", "id": req["id"], "result": null }));
+        assert_eq!(server.read()["method"], "exit");
+        done.join().unwrap();
+    }
+
+    #[test]
+    fn exits_after_unanswered_shutdown() {
+        let (client, mut server) = connect(|_, _| ());
+        server.initialize(json!({}));
+        let done = thread::spawn(move || client.shutdown());
+        assert_eq!(server.read()["method"], "shutdown");
+        assert_eq!(server.read()["method"], "exit");
+        done.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn talks_to_a_child() {
+        let args = ["-c".to_string(), FAKE_SERVER.to_string()];
+        let client = Client::spawn("sh", &args, &std::env::temp_dir(), |_, _| ()).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let params = json!({ "text": "é😀" });
+        client.request("textDocument/hover", params.clone(), move |res| {
+            tx.send(res).unwrap()
+        });
+        let echoed = rx.recv_timeout(TIMEOUT).unwrap().unwrap();
+        assert_eq!(echoed["method"], "textDocument/hover");
+        assert_eq!(echoed["params"], params);
+        assert_eq!(client.capabilities()["hoverProvider"], true);
+        // it exits by itself rather than being killed at the timeout
+        let start = std::time::Instant::now();
+        client.shutdown();
+        assert!(start.elapsed() < EXIT_TIMEOUT);
+        assert!(client.child.lock().unwrap().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn survives_a_dead_child() {
+        let args = ["-c".to_string(), "exit 1".to_string()];
+        let client = Client::spawn("sh", &args, &std::env::temp_dir(), |_, _| ()).unwrap();
+        client.request("textDocument/hover", Value::Null, |_| ());
+        client.shutdown();
+        assert!(
+            Client::spawn("red-no-such-server", &[], &std::env::temp_dir(), |_, _| ()).is_err()
+        );
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn converts_uris() {
+        let path = Path::new("/home/me/my project/ünï.rs");
+        let uri = path_to_uri(path);
+        assert_eq!(uri, "file:///home/me/my%20project/%C3%BCn%C3%AF.rs");
+        assert_eq!(uri_to_path(&uri).as_deref(), Some(path));
+        assert_eq!(
+            uri_to_path("file:///a/%5Bb%5d.rs").as_deref(),
+            Some(Path::new("/a/[b].rs"))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_uris() {
+        assert_eq!(uri_to_path("https://example.com/a.rs"), None);
+        assert_eq!(uri_to_path("file:///a%2"), None);
+        assert_eq!(uri_to_path("file:///a%zz"), None);
+    }
+
+    #[test]
+    fn converts_windows_paths() {
+        assert_eq!(
+            path_to_uri(Path::new("C:\\src\\a b.rs")),
+            "file:///C:/src/a%20b.rs"
+        );
+    }
+}
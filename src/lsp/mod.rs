@@ -0,0 +1,631 @@
+use crate::{
+    config::{Config, LanguageServer},
+    diagnostics::{self, Diagnostic, Severity},
+    dialogs, references,
+    state::STATE,
+};
+use fltk::{app, prelude::*, text, utils::oncelock::Lazy};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+mod client;
+
+use client::{path_to_uri, uri_to_path, Client, ResponseError, SyncKind};
+
+// the running servers by language id, None if one failed to start
+static SERVERS: Lazy<Mutex<HashMap<String, Option<Arc<Client>>>>> = Lazy::new(Default::default);
+static DOCUMENTS: Lazy<Mutex<HashMap<PathBuf, Document>>> = Lazy::new(Default::default);
+
+/// An open file synced with its server
+struct Document {
+    client: Arc<Client>,
+    language: String,
+    uri: String,
+    version: i64,
+    buf: text::TextBuffer,
+    /// Where its buffer's edits are sent, which follows renames
+    key: Arc<Mutex<PathBuf>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Position {
+    line: usize,
+    character: usize,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct Range {
+    start: Position,
+    end: Position,
+}
+
+/// A `Location` or a `LocationLink`
+#[derive(Debug, Clone, Deserialize)]
+struct Location {
+    #[serde(alias = "targetUri")]
+    uri: String,
+    #[serde(alias = "targetSelectionRange")]
+    range: Range,
+}
+
+/// A completion candidate offered by a server
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub label: String,
+    pub insert: String,
+    /// The bytes `insert` replaces, when the server says which
+    pub range: Option<std::ops::Range<i32>>,
+    pub detail: String,
+    pub documentation: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Locations {
+    One(Location),
+    Many(Vec<Location>),
+}
+
+/// Runs `f` on the main thread
+fn on_main(f: impl FnOnce() + Send + 'static) {
+    let mut f = Some(f);
+    app::awake_callback(move || {
+        if let Some(f) = f.take() {
+            f();
+        }
+    });
+}
+
+fn on_notification(method: &str, params: Value) {
+    match method {
+        "textDocument/publishDiagnostics" => on_main(move || publish_diagnostics(&params)),
+        "window/showMessage" if params["type"].as_u64() == Some(1) => {
+            eprintln!("{}", params["message"].as_str().unwrap_or_default());
+        }
+        _ => (),
+    }
+}
+
+/// The server of `language`, started on first use
+fn server(language: &str, config: &LanguageServer, root: &Path) -> Option<Arc<Client>> {
+    SERVERS
+        .lock()
+        .unwrap()
+        .entry(language.to_string())
+        .or_insert_with(|| {
+            match Client::spawn(&config.command, &config.args, root, on_notification) {
+                Ok(client) => Some(client),
+                Err(e) => {
+                    eprintln!("Failed to start {}: {}", config.command, e);
+                    None
+                }
+            }
+        })
+        .clone()
+}
+
+/// The position of byte `pos` of `buf`, in UTF-16 code units as servers expect
+fn position(buf: &text::TextBuffer, pos: i32) -> Position {
+    let start = buf.line_start(pos);
+    Position {
+        line: buf.count_lines(0, pos) as usize,
+        character: buf
+            .text_range(start, pos)
+            .unwrap_or_default()
+            .encode_utf16()
+            .count(),
+    }
+}
+
+/// The position at the end of `text` inserted at `start`
+fn advance(start: Position, text: &str) -> Position {
+    match text.rfind('\n') {
+        Some(i) => Position {
+            line: start.line + text.matches('\n').count(),
+            character: text[i + 1..].encode_utf16().count(),
+        },
+        None => Position {
+            line: start.line,
+            character: start.character + text.encode_utf16().count(),
+        },
+    }
+}
+
+/// The byte offset of `p` in `buf`, the reverse of `position`
+fn offset(buf: &text::TextBuffer, p: Position) -> i32 {
+    let start = buf.clone().skip_lines(0, p.line as i32);
+    let line = buf.line_text(start);
+    let mut units = 0;
+    let len: usize = line
+        .chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= p.character
+        })
+        .map(char::len_utf8)
+        .sum();
+    start + len as i32
+}
+
+/// The 1-based char column of a UTF-16 `character` in `line`
+fn char_col(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    line.chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= character
+        })
+        .count()
+        + 1
+}
+
+/// The text of `path`, from its editor if it's open
+fn file_text(path: &Path) -> String {
+    match DOCUMENTS.lock().unwrap().get(path) {
+        Some(doc) => doc.buf.text(),
+        None => fs::read_to_string(path).unwrap_or_default(),
+    }
+}
+
+fn publish_diagnostics(params: &Value) {
+    let Some(path) = params["uri"].as_str().and_then(uri_to_path) else {
+        return;
+    };
+    let path = path.canonicalize().unwrap_or(path);
+    let text = file_text(&path);
+    let lines: Vec<_> = text.lines().collect();
+    let point = |p: Position| {
+        let col = char_col(lines.get(p.line).copied().unwrap_or_default(), p.character);
+        (p.line + 1, col)
+    };
+    let found = params["diagnostics"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|d| {
+            let range: Range = serde_json::from_value(d["range"].clone()).ok()?;
+            let (line, col) = point(range.start);
+            let (end_line, end_col) = point(range.end);
+            Some(Diagnostic {
+                path: path.clone(),
+                line,
+                col,
+                end_line,
+                end_col,
+                severity: match d["severity"].as_u64() {
+                    Some(2) => Severity::Warning,
+                    Some(3) | Some(4) => Severity::Info,
+                    _ => Severity::Error,
+                },
+                message: d["message"].as_str().unwrap_or_default().to_string(),
+            })
+        })
+        .collect();
+    diagnostics::publish(&format!("lsp {}", path.display()), found);
+}
+
+/// Opens `path` with the server configured for its extension and keeps it synced with `buf`
+pub fn attach(buf: &mut text::TextBuffer, path: &Path, config: &Config, root: &Path) {
+    let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+        return;
+    };
+    let Some((language, server_config)) = config
+        .language_servers
+        .iter()
+        .find(|(_, s)| s.extensions.iter().any(|e| e == ext))
+    else {
+        return;
+    };
+    let Some(client) = server(language, server_config, root) else {
+        return;
+    };
+    let key = Arc::new(Mutex::new(path.to_path_buf()));
+    let doc = Document {
+        client,
+        language: language.clone(),
+        uri: path_to_uri(path),
+        version: 0,
+        buf: buf.clone(),
+        key: key.clone(),
+    };
+    open_document(&doc);
+    DOCUMENTS.lock().unwrap().insert(path.to_path_buf(), doc);
+    buf.add_modify_callback(move |pos, inserted, deleted, _, deleted_text| {
+        if inserted > 0 || deleted > 0 {
+            let path = key.lock().unwrap().clone();
+            changed(&path, pos, inserted, deleted_text.unwrap_or_default());
+        }
+    });
+}
+
+fn open_document(doc: &Document) {
+    doc.client.notify(
+        "textDocument/didOpen",
+        json!({
+            "textDocument": {
+                "uri": doc.uri,
+                "languageId": doc.language,
+                "version": doc.version,
+                "text": doc.buf.text(),
+            }
+        }),
+    );
+}
+
+/// Sends the edit at `pos` that replaced the `deleted` text with `inserted` bytes
+fn changed(path: &Path, pos: i32, inserted: i32, deleted: &str) {
+    let mut docs = DOCUMENTS.lock().unwrap();
+    let Some(doc) = docs.get_mut(path) else {
+        return;
+    };
+    let change = match doc.client.sync_kind() {
+        SyncKind::None => return,
+        SyncKind::Full => json!({ "text": doc.buf.text() }),
+        SyncKind::Incremental => {
+            // the text before `pos` is unchanged, so its position is too
+            let start = position(&doc.buf, pos);
+            json!({
+                "range": {
+                    "start": start,
+                    "end": advance(start, deleted),
+                },
+                "text": doc.buf.text_range(pos, pos + inserted).unwrap_or_default(),
+            })
+        }
+    };
+    doc.version += 1;
+    doc.client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": doc.uri, "version": doc.version },
+            "contentChanges": [change],
+        }),
+    );
+}
+
+pub fn saved(path: &Path) {
+    if let Some(doc) = DOCUMENTS.lock().unwrap().get(path) {
+        let mut params = json!({ "textDocument": { "uri": doc.uri } });
+        if doc.client.capabilities()["textDocumentSync"]["save"]["includeText"] == true {
+            params["text"] = doc.buf.text().into();
+        }
+        doc.client.notify("textDocument/didSave", params);
+    }
+}
+
+fn close_document(doc: &Document) {
+    doc.client.notify(
+        "textDocument/didClose",
+        json!({ "textDocument": { "uri": doc.uri } }),
+    );
+}
+
+pub fn close(path: &Path) {
+    if let Some(doc) = DOCUMENTS.lock().unwrap().remove(path) {
+        close_document(&doc);
+    }
+}
+
+/// Reopens the documents of `old`, or of the files under it, at `new`
+pub fn rename(old: &Path, new: &Path) {
+    let mut docs = DOCUMENTS.lock().unwrap();
+    let moved: Vec<_> = docs
+        .keys()
+        .filter_map(|p| {
+            let rest = p.strip_prefix(old).ok()?;
+            let to = if rest.as_os_str().is_empty() {
+                new.to_path_buf()
+            } else {
+                new.join(rest)
+            };
+            Some((p.clone(), to))
+        })
+        .collect();
+    for (from, to) in moved {
+        let mut doc = docs.remove(&from).unwrap();
+        close_document(&doc);
+        doc.uri = path_to_uri(&to);
+        doc.version = 0;
+        open_document(&doc);
+        *doc.key.lock().unwrap() = to.clone();
+        docs.insert(to, doc);
+    }
+}
+
+/// Stops every server, e.g. when quitting
+pub fn shutdown() {
+    let servers: Vec<_> = SERVERS.lock().unwrap().drain().collect();
+    for client in servers.into_iter().filter_map(|(_, c)| c) {
+        client.shutdown();
+    }
+}
+
+/// Sends a request about byte `pos` of `path`, with the fields of `extra` added to its params,
+/// `f` getting its result on the main thread. Returns false if no server handles the file
+fn request_at(
+    path: &Path,
+    pos: i32,
+    method: &str,
+    extra: Value,
+    f: impl FnOnce(Result<Value, ResponseError>) + Send + 'static,
+) -> bool {
+    let found = DOCUMENTS.lock().unwrap().get(path).map(|doc| {
+        let mut params = json!({
+            "textDocument": { "uri": doc.uri },
+            "position": position(&doc.buf, pos),
+        });
+        if let (Some(params), Value::Object(extra)) = (params.as_object_mut(), extra) {
+            params.extend(extra);
+        }
+        (doc.client.clone(), params)
+    });
+    let Some((client, params)) = found else {
+        return false;
+    };
+    client.request(method, params, move |res| on_main(move || f(res)));
+    true
+}
+
+/// Sends a request about the cursor position of the current editor,
+/// `f` getting its result on the main thread
+fn request_at_cursor(
+    method: &str,
+    extra: Value,
+    f: impl FnOnce(text::TextEditor, i32, Result<Value, ResponseError>) + Send + 'static,
+) {
+    let Some((ed, path)) = STATE.with(|s| Some((s.current_editor()?, s.current_file()?))) else {
+        return;
+    };
+    let pos = ed.insert_position();
+    if !request_at(&path, pos, method, extra, move |res| f(ed, pos, res)) {
+        fltk::dialog::message_default("No language server is running for this file");
+    }
+}
+
+/// Opens the definition of the symbol under the cursor
+pub fn goto_definition() {
+    request_at_cursor("textDocument/definition", Value::Null, |_, _, res| {
+        let locations = match res.map(serde_json::from_value::<Option<Locations>>) {
+            Ok(Ok(Some(Locations::One(l)))) => vec![l],
+            Ok(Ok(Some(Locations::Many(v)))) => v,
+            Ok(_) => vec![],
+            Err(e) => {
+                eprintln!("Definition request failed: {}", e);
+                return;
+            }
+        };
+        match locations.first() {
+            Some(l) => open_location(l),
+            None => fltk::dialog::message_default("No definition found"),
+        }
+    });
+}
+
+/// The file of `l` and its 1-based line and char column, with the text of that line
+fn resolve(l: &Location) -> Option<(PathBuf, usize, usize, String)> {
+    let path = uri_to_path(&l.uri)?;
+    let path = path.canonicalize().unwrap_or(path);
+    let text = file_text(&path);
+    let line = text.lines().nth(l.range.start.line).unwrap_or_default();
+    let col = char_col(line, l.range.start.character);
+    Some((path, l.range.start.line + 1, col, line.to_string()))
+}
+
+fn open_location(l: &Location) {
+    if let Some((path, line, col, _)) = resolve(l) {
+        dialogs::open_at(&path, line as i32, col as i32);
+    }
+}
+
+/// Lists the references to the symbol under the cursor in the references panel
+pub fn find_references() {
+    let context = json!({ "context": { "includeDeclaration": true } });
+    request_at_cursor("textDocument/references", context, |_, _, res| {
+        let locations = match res.map(serde_json::from_value::<Option<Vec<Location>>>) {
+            Ok(Ok(v)) => v.unwrap_or_default(),
+            Ok(Err(e)) => {
+                eprintln!("Invalid references: {}", e);
+                return;
+            }
+            Err(e) => {
+                eprintln!("References request failed: {}", e);
+                return;
+            }
+        };
+        let found = locations
+            .iter()
+            .filter_map(resolve)
+            .map(|(path, line, col, text)| references::Reference {
+                path,
+                line,
+                col,
+                text: text.trim().to_string(),
+            })
+            .collect();
+        references::set(found);
+    });
+}
+
+/// The plain text of hover contents or documentation,
+/// which can be a string, markup or a list of either
+fn markup_text(contents: &Value) -> String {
+    let text = match contents {
+        Value::String(s) => s.clone(),
+        Value::Array(v) => v.iter().map(markup_text).collect::<Vec<_>>().join("\n\n"),
+        Value::Object(o) => o
+            .get("value")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        _ => String::new(),
+    };
+    // drop the markdown code fences
+    let lines: Vec<_> = text
+        .lines()
+        .filter(|l| !l.trim_start().starts_with("```"))
+        .collect();
+    lines.join("\n").trim().to_string()
+}
+
+/// Shows the documentation of the symbol under the cursor
+pub fn hover() {
+    request_at_cursor(
+        "textDocument/hover",
+        Value::Null,
+        |ed, pos, res| match res {
+            Ok(v) => {
+                let text = markup_text(&v["contents"]);
+                if !text.is_empty() {
+                    dialogs::show_popup(&ed, pos, &text);
+                }
+            }
+            Err(e) => eprintln!("Hover request failed: {}", e),
+        },
+    );
+}
+
+/// The bytes of `buf` replaced by a completion's `edit`
+fn edit_range(buf: &text::TextBuffer, edit: &Value) -> Option<std::ops::Range<i32>> {
+    // an `InsertReplaceEdit` has a range for inserting and one for replacing
+    let range = edit.get("range").or(edit.get("insert"))?;
+    let range: Range = serde_json::from_value(range.clone()).ok()?;
+    Some(offset(buf, range.start)..offset(buf, range.end))
+}
+
+/// Asks the server of `path` for completions at byte `pos`, `f` getting them
+/// on the main thread. Returns false if no server handles the file
+pub fn completions(
+    path: &Path,
+    pos: i32,
+    f: impl FnOnce(Vec<Completion>) + Send + 'static,
+) -> bool {
+    let file = path.to_path_buf();
+    request_at(
+        path,
+        pos,
+        "textDocument/completion",
+        Value::Null,
+        move |res| {
+            let res = match res {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("Completion request failed: {}", e);
+                    Value::Null
+                }
+            };
+            // either a list or an object with the list in `items`
+            let items = res.get("items").unwrap_or(&res);
+            let buf = DOCUMENTS.lock().unwrap().get(&file).map(|d| d.buf.clone());
+            let completions = items
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|item| {
+                    let label = item["label"].as_str().unwrap_or_default().to_string();
+                    let edit = &item["textEdit"];
+                    let insert = edit["newText"]
+                        .as_str()
+                        .or(item["insertText"].as_str())
+                        .unwrap_or(&label)
+                        .to_string();
+                    Completion {
+                        insert,
+                        range: buf.as_ref().and_then(|buf| edit_range(buf, edit)),
+                        detail: item["detail"].as_str().unwrap_or_default().to_string(),
+                        documentation: markup_text(&item["documentation"]),
+                        label,
+                    }
+                })
+                .collect();
+            f(completions);
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(line: usize, character: usize) -> Position {
+        Position { line, character }
+    }
+
+    fn same(a: Position, b: Position) -> bool {
+        (a.line, a.character) == (b.line, b.character)
+    }
+
+    #[test]
+    fn counts_utf16_positions() {
+        let mut buf = text::TextBuffer::default();
+        // é takes 2 bytes and 1 unit, 😀 takes 4 bytes and 2 units
+        buf.set_text("let é = 1;\nlet 😀x = 2;\n");
+        assert!(same(position(&buf, 0), pos(0, 0)));
+        assert!(same(position(&buf, 7), pos(0, 6)));
+        let second = "let é = 1;\n".len() as i32;
+        assert!(same(position(&buf, second), pos(1, 0)));
+        assert!(same(position(&buf, second + 8), pos(1, 6)));
+    }
+
+    #[test]
+    fn advances_over_inserted_text() {
+        assert!(same(advance(pos(3, 4), ""), pos(3, 4)));
+        assert!(same(advance(pos(3, 4), "é😀"), pos(3, 7)));
+        assert!(same(advance(pos(3, 4), "a\nbc\n"), pos(5, 0)));
+        assert!(same(advance(pos(3, 4), "a\n😀b"), pos(4, 3)));
+    }
+
+    #[test]
+    fn maps_utf16_to_char_columns() {
+        let line = "a😀b";
+        assert_eq!(char_col(line, 0), 1);
+        assert_eq!(char_col(line, 1), 2);
+        // inside the surrogate pair
+        assert_eq!(char_col(line, 2), 2);
+        assert_eq!(char_col(line, 3), 3);
+        assert_eq!(char_col(line, 4), 4);
+        assert_eq!(char_col(line, 10), 4);
+    }
+
+    #[test]
+    fn parses_definition_results() {
+        let location = json!({
+            "uri": "file:///a.rs",
+            "range": { "start": { "line": 1, "character": 2 }, "end": { "line": 1, "character": 3 } },
+        });
+        let link = json!({
+            "targetUri": "file:///b.rs",
+            "targetRange": location["range"],
+            "targetSelectionRange": location["range"],
+        });
+        match serde_json::from_value(location.clone()).unwrap() {
+            Some(Locations::One(l)) => assert_eq!(l.uri, "file:///a.rs"),
+            l => panic!("{:?}", l),
+        }
+        match serde_json::from_value(json!([location, link])).unwrap() {
+            Some(Locations::Many(v)) => {
+                assert_eq!(v[1].uri, "file:///b.rs");
+                assert!(same(v[1].range.start, pos(1, 2)));
+            }
+            l => panic!("{:?}", l),
+        }
+        assert!(serde_json::from_value::<Option<Locations>>(Value::Null)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn strips_markup() {
+        let contents = json!([
+            { "language": "rust", "value": "fn f()" },
+            { "kind": "markdown", "value": "```rust\nlet x;\n```\nDocs" },
+        ]);
+        assert_eq!(markup_text(&contents), "fn f()\n\nlet x;\nDocs");
+        assert_eq!(markup_text(&Value::Null), "");
+    }
+}
@@ -1,18 +1,51 @@
 use std::env;
 
+mod blame;
 mod cbs;
+mod commands;
+mod completion;
+mod config;
+mod conflict;
+mod diagnostics;
 mod dialogs;
+mod diff;
+mod diffview;
 mod fbr;
+mod git;
 mod gui;
+mod gutter;
+mod history;
+mod lsp;
+mod navigation;
+mod output;
+mod problems;
+mod references;
+mod squiggle;
 mod state;
+mod tasks;
 mod utils;
 
 #[cfg(feature = "highlight")]
 mod highlight;
+#[cfg(feature = "highlight")]
+mod outline;
+#[cfg(feature = "term")]
+mod terminal;
 
 fn main() {
-    let (current_file, current_path) = utils::init_args(env::args());
+    let diff_files = utils::diff_args(env::args());
+    let (current_file, current_path) = match &diff_files {
+        // `red --diff a b` starts with the browser hidden, like when opening a file
+        Some((a, _)) => (Some(a.clone()), utils::current_dir()),
+        None => utils::init_args(env::args()),
+    };
+    state::init_state(current_path.clone());
     let a = gui::init_gui(&current_file, &current_path);
-    state::init_state(current_file, current_path);
+    match diff_files {
+        Some((old, new)) => diffview::compare_files(&old, &new),
+        None => state::STATE.with(move |s| s.append(current_file.clone())),
+    }
+    git::refresh();
     a.run().unwrap();
+    lsp::shutdown();
 }
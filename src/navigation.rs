@@ -0,0 +1,107 @@
+use crate::{dialogs, lsp, state::STATE};
+use fltk::{enums::Event, prelude::*, *};
+use std::{path::PathBuf, sync::Mutex};
+
+const MAX_HISTORY: usize = 100;
+
+/// A cursor position, with a 1-based line and col
+#[derive(Debug, Clone, PartialEq)]
+struct Location {
+    path: PathBuf,
+    line: i32,
+    col: i32,
+}
+
+static BACK: Mutex<Vec<Location>> = Mutex::new(Vec::new());
+static FORWARD: Mutex<Vec<Location>> = Mutex::new(Vec::new());
+
+/// Where the cursor of the current editor is, if it's showing a file
+fn current() -> Option<Location> {
+    let (ed, path) = STATE.with(|s| Some((s.current_editor()?, s.current_file()?)))?;
+    let buf = ed.buffer()?;
+    let pos = ed.insert_position();
+    let start = buf.line_start(pos);
+    Some(Location {
+        path,
+        line: buf.count_lines(0, pos) + 1,
+        col: buf
+            .text_range(start, pos)
+            .unwrap_or_default()
+            .chars()
+            .count() as i32
+            + 1,
+    })
+}
+
+fn push(stack: &Mutex<Vec<Location>>, loc: Location) {
+    let mut stack = stack.lock().unwrap();
+    if stack.last() != Some(&loc) {
+        stack.push(loc);
+    }
+    if stack.len() > MAX_HISTORY {
+        stack.remove(0);
+    }
+}
+
+/// Makes Ctrl+click in `ed` jump to the definition under the pointer.
+/// Its tab sees the click first, since the gutter handles the editor's events
+pub fn attach(ed: &text::TextEditor) {
+    let Some(mut row) = ed.parent() else {
+        return;
+    };
+    let mut ed = ed.clone();
+    row.handle(move |_, ev| match ev {
+        // the margin keeps its own clicks
+        Event::Push
+            if app::is_event_command()
+                && app::event_inside_widget(&ed)
+                && app::event_x() >= ed.x() + ed.linenumber_width() =>
+        {
+            let pos = ed.xy_to_position(
+                app::event_x(),
+                app::event_y(),
+                text::PositionType::Character,
+            );
+            ed.set_insert_position(pos);
+            ed.take_focus().ok();
+            lsp::goto_definition();
+            true
+        }
+        _ => false,
+    });
+}
+
+/// Remembers the cursor position before a jump elsewhere
+pub fn record() {
+    if let Some(loc) = current() {
+        push(&BACK, loc);
+        FORWARD.lock().unwrap().clear();
+    }
+}
+
+/// Moves to the last location of `from`, remembering the current one in `to`
+fn walk(from: &Mutex<Vec<Location>>, to: &Mutex<Vec<Location>>) {
+    let here = current();
+    let mut target = from.lock().unwrap().pop();
+    // skip the entries pointing where we already are
+    while target.is_some() && target == here {
+        target = from.lock().unwrap().pop();
+    }
+    let Some(target) = target else {
+        return;
+    };
+    if let Some(here) = here {
+        push(to, here);
+    }
+    if target.path.exists() {
+        dialogs::show_position(&target.path, target.line, target.col);
+    }
+}
+
+pub fn back() {
+    walk(&BACK, &FORWARD);
+}
+
+pub fn forward() {
+    walk(&FORWARD, &BACK);
+}
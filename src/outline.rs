@@ -0,0 +1,413 @@
+use crate::{highlight, navigation, state::STATE};
+use fltk::{enums::*, prelude::*, *};
+use std::{
+    collections::HashSet,
+    ops::Range,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+use tree_sitter::{Parser, Query, QueryCursor};
+
+// how often the outline checks for tab switches and cursor moves
+const POLL: f64 = 0.5;
+// how long edits pause before the outline is rebuilt
+const DELAY: f64 = 0.3;
+
+/// A definition found by the outline query of a language
+#[derive(Debug, Clone)]
+struct Symbol {
+    label: String,
+    range: Range<usize>,
+    /// The level of markdown headings, which don't contain their sections
+    level: Option<usize>,
+}
+
+/// What the outline currently lists
+struct Shown {
+    /// The editor the symbols come from
+    editor: usize,
+    /// The tree path of each symbol and its range
+    entries: Vec<(String, Range<usize>)>,
+    /// The last cursor position the selection was synced with
+    cursor: i32,
+}
+
+static SHOWN: Mutex<Option<Shown>> = Mutex::new(None);
+// whether a buffer's edits paused since the outline was built
+static DIRTY: AtomicBool = AtomicBool::new(true);
+
+/// How a definition of `kind` named `name` is listed, `def` being its whole text
+fn label(kind: &str, name: &str, def: &str) -> String {
+    match kind {
+        "impl" => def
+            .lines()
+            .next()
+            .unwrap_or(name)
+            .trim_end_matches(|c: char| c == '{' || c.is_whitespace())
+            .to_string(),
+        "function" => format!("fn {}", name),
+        "module" => format!("mod {}", name),
+        "constant" => format!("const {}", name),
+        "macro" => format!("{}!", name),
+        "table" => format!("[{}]", name),
+        "table_array" => format!("[[{}]]", name),
+        "heading" => name.to_string(),
+        kind => format!("{} {}", kind, name),
+    }
+}
+
+fn heading_level(def: &str) -> usize {
+    match def.trim_start().bytes().take_while(|&b| b == b'#').count() {
+        // setext headings are underlined with = or -
+        0 if def.trim_end().ends_with('=') => 1,
+        0 => 2,
+        n => n,
+    }
+}
+
+/// The definitions of `text`, a file like `p`, in order
+fn symbols(p: &Path, text: &str) -> Vec<Symbol> {
+    let Some((language, source)) = highlight::outline_query(p) else {
+        return vec![];
+    };
+    let mut parser = Parser::new();
+    if parser.set_language(language).is_err() {
+        return vec![];
+    }
+    let Some(tree) = parser.parse(text, None) else {
+        return vec![];
+    };
+    let query = match Query::new(language, source) {
+        Ok(q) => q,
+        Err(e) => {
+            eprintln!("Invalid outline query: {}", e);
+            return vec![];
+        }
+    };
+    let names = query.capture_names();
+    let mut cursor = QueryCursor::new();
+    let mut v = vec![];
+    for m in cursor.matches(&query, tree.root_node(), text.as_bytes()) {
+        let mut name = None;
+        let mut def = None;
+        for c in m.captures {
+            match names[c.index as usize].as_str() {
+                "name" => name = Some(c.node),
+                capture => {
+                    if let Some(kind) = capture.strip_prefix("definition.") {
+                        def = Some((kind, c.node));
+                    }
+                }
+            }
+        }
+        let (Some(name), Some((kind, node))) = (name, def) else {
+            continue;
+        };
+        let name = name.utf8_text(text.as_bytes()).unwrap_or_default().trim();
+        let def = &text[node.byte_range()];
+        v.push(Symbol {
+            label: label(kind, name, def),
+            range: node.byte_range(),
+            level: (kind == "heading").then(|| heading_level(def)),
+        });
+    }
+    v.sort_by_key(|s| (s.range.start, std::cmp::Reverse(s.range.end)));
+    // a heading's section lasts until the next heading of the same level or above
+    for i in 0..v.len() {
+        if let Some(level) = v[i].level {
+            let end = v[i + 1..]
+                .iter()
+                .find(|s| s.level.is_some_and(|l| l <= level))
+                .map_or(text.len(), |s| s.range.start);
+            v[i].range.end = end;
+        }
+    }
+    v
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('/', "\\/")
+}
+
+/// The tree path of `item`, as it was added
+fn item_path(item: &tree::TreeItem) -> String {
+    let mut parts = vec![];
+    let mut it = Some(item.clone());
+    while let Some(i) = it {
+        if i.is_root() {
+            break;
+        }
+        parts.push(escape(&i.label().unwrap_or_default()));
+        it = i.parent();
+    }
+    parts.reverse();
+    parts.join("/")
+}
+
+/// Adds the outline tab to the bottom panel
+pub fn init(panel: &mut group::Tabs) {
+    panel.begin();
+    let mut t = tree::Tree::default()
+        .with_label("Outline")
+        .with_id("outline");
+    t.set_show_root(false);
+    t.set_select_mode(tree::TreeSelect::Single);
+    t.set_trigger(CallbackTrigger::Changed);
+    t.set_item_reselect_mode(tree::TreeItemReselectMode::Always);
+    t.set_connector_style(tree::TreeConnectorStyle::None);
+    t.set_color(Color::Background2);
+    t.set_selection_color(Color::Selection);
+    t.set_item_label_fgcolor(Color::Foreground);
+    t.set_item_label_size(12);
+    t.set_callback(|t| {
+        if matches!(
+            t.callback_reason(),
+            tree::TreeReason::Selected | tree::TreeReason::Reselected
+        ) {
+            if let Some(item) = t.callback_item() {
+                goto(&item_path(&item));
+            }
+        }
+    });
+    panel.end();
+    panel.auto_layout();
+    app::add_timeout3(POLL, |h| {
+        poll();
+        app::repeat_timeout3(POLL, h);
+    });
+}
+
+fn outline() -> tree::Tree {
+    app::widget_from_id("outline").unwrap()
+}
+
+/// Rebuilds the outline once the edits of `buf` pause
+pub fn attach(buf: &mut text::TextBuffer) {
+    let mut handle: Option<app::TimeoutHandle> = None;
+    buf.add_modify_callback(move |_, inserted, deleted, _, _| {
+        if inserted > 0 || deleted > 0 {
+            if let Some(h) = handle.take() {
+                app::remove_timeout3(h);
+            }
+            handle = Some(app::add_timeout3(DELAY, |_| {
+                DIRTY.store(true, Ordering::Relaxed);
+                poll();
+            }));
+        }
+    });
+}
+
+/// The tree path of each of the `symbols` of `text`, under the ones containing it
+fn tree_paths(text: &str, symbols: Vec<Symbol>) -> Vec<(String, Range<usize>)> {
+    // the enclosing symbols of the current one, with their tree paths
+    let mut stack: Vec<(Range<usize>, String)> = vec![];
+    let mut used = HashSet::new();
+    let mut entries = vec![];
+    for s in symbols {
+        while stack
+            .last()
+            .is_some_and(|(r, _)| !(r.start <= s.range.start && s.range.end <= r.end))
+        {
+            stack.pop();
+        }
+        let parent = stack.last().map(|(_, p)| format!("{}/", p));
+        let mut item_path = format!("{}{}", parent.unwrap_or_default(), escape(&s.label));
+        if !used.insert(item_path.clone()) {
+            // tell apart the definitions with the same label
+            let line = text[..s.range.start].matches('\n').count() + 1;
+            item_path = format!("{} ({})", item_path, line);
+            used.insert(item_path.clone());
+        }
+        stack.push((s.range.clone(), item_path.clone()));
+        entries.push((item_path, s.range));
+    }
+    entries
+}
+
+/// Lists the definitions of the current editor's file
+fn refresh(ed: Option<text::TextEditor>, path: Option<&Path>) {
+    let mut t = outline();
+    t.clear();
+    let entries = match (ed.as_ref(), path) {
+        (Some(ed), Some(path)) => {
+            let text = ed.buffer().unwrap().text();
+            tree_paths(&text, symbols(path, &text))
+        }
+        _ => vec![],
+    };
+    for (p, _) in &entries {
+        t.add(p);
+    }
+    *SHOWN.lock().unwrap() = Some(Shown {
+        editor: ed.map_or(0, |e| e.as_widget_ptr() as usize),
+        entries,
+        cursor: -1,
+    });
+    t.redraw();
+}
+
+/// Selects the innermost definition containing the cursor of `ed`
+fn sync_cursor(ed: &text::TextEditor) {
+    let pos = ed.insert_position();
+    let found = {
+        let mut shown = SHOWN.lock().unwrap();
+        let Some(shown) = shown.as_mut().filter(|s| s.cursor != pos) else {
+            return;
+        };
+        shown.cursor = pos;
+        shown
+            .entries
+            .iter()
+            .filter(|(_, r)| r.contains(&(pos as usize)))
+            .min_by_key(|(_, r)| r.len())
+            .map(|(p, _)| p.clone())
+    };
+    let mut t = outline();
+    match found.and_then(|p| t.find_item(&p)) {
+        Some(item) => {
+            t.select_only(&item, false).ok();
+            t.show_item_middle(&item);
+        }
+        None => {
+            if let Some(root) = t.root() {
+                t.deselect_all(&root, false).ok();
+            }
+        }
+    }
+    t.redraw();
+}
+
+fn poll() {
+    let t = outline();
+    if !t.visible_r() {
+        return;
+    }
+    let (ed, path) = STATE.with(|s| (s.current_editor(), s.current_file()));
+    let editor = ed.as_ref().map_or(0, |e| e.as_widget_ptr() as usize);
+    let switched = SHOWN.lock().unwrap().as_ref().map(|s| s.editor) != Some(editor);
+    if switched || DIRTY.swap(false, Ordering::Relaxed) {
+        refresh(ed.clone(), path.as_deref());
+    }
+    if let Some(ed) = ed {
+        sync_cursor(&ed);
+    }
+}
+
+/// Moves the cursor of the current editor to the definition listed at `item_path`
+fn goto(item_path: &str) {
+    let start = SHOWN
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|s| s.entries.iter().find(|(p, _)| p == item_path))
+        .map(|(_, r)| r.start as i32);
+    let Some(start) = start else {
+        return;
+    };
+    if let Some(mut ed) = STATE.with(|s| s.current_editor()) {
+        navigation::record();
+        ed.set_insert_position(start);
+        ed.show_insert_position();
+        ed.take_focus().ok();
+    }
+}
+
+/// Shows the outline tab, showing the panel if it's hidden
+pub fn show() {
+    let mut panel: group::Tabs = app::widget_from_id("panel").unwrap();
+    panel.set_value(&outline()).ok();
+    crate::cbs::show_panel();
+    panel.redraw();
+    poll();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outline_of(file: &str, text: &str) -> Vec<String> {
+        tree_paths(text, symbols(Path::new(file), text))
+            .into_iter()
+            .map(|(p, _)| p)
+            .collect()
+    }
+
+    #[test]
+    fn labels_definitions() {
+        let def = "impl<T: Clone> Tr for S<T> {\n}";
+        assert_eq!(label("impl", "S<T>", def), "impl<T: Clone> Tr for S<T>");
+        assert_eq!(label("function", "f", ""), "fn f");
+        assert_eq!(label("macro", "m", ""), "m!");
+        assert_eq!(label("table_array", "bin", ""), "[[bin]]");
+        assert_eq!(label("struct", "S", ""), "struct S");
+    }
+
+    #[test]
+    fn levels_headings() {
+        assert_eq!(heading_level("## Usage\n"), 2);
+        assert_eq!(heading_level("   ### Detail"), 3);
+        assert_eq!(heading_level("Title\n=====\n"), 1);
+        assert_eq!(heading_level("Section\n---"), 2);
+    }
+
+    #[test]
+    fn outlines_rust() {
+        let text = "mod a {\n    struct S;\n    impl S {\n        fn new() -> Self {\n            S\n        }\n    }\n}\n\nfn f() {}\nfn f() {}\nconst N: u32 = 1;\nmacro_rules! m {\n    () => {};\n}\n";
+        assert_eq!(
+            outline_of("lib.rs", text),
+            [
+                "mod a",
+                "mod a/struct S",
+                "mod a/impl S",
+                "mod a/impl S/fn new",
+                "fn f",
+                "fn f (11)",
+                "const N",
+                "m!",
+            ]
+        );
+    }
+
+    #[test]
+    fn outlines_toml() {
+        let text = "[package]\nname = \"x\"\n\n[dependencies.serde]\nversion = \"1\"\n\n[[bin]]\nname = \"a\"\n";
+        assert_eq!(
+            outline_of("Cargo.toml", text),
+            ["[package]", "[dependencies.serde]", "[[bin]]"]
+        );
+    }
+
+    #[test]
+    fn outlines_markdown() {
+        let text = "# Title\n\nIntro\n\n## Usage\n\nText\n\n### Detail\n\nMore\n\nSetext\n------\n\nOther\n=====\n\nEnd\n";
+        assert_eq!(
+            outline_of("README.md", text),
+            [
+                "Title",
+                "Title/Usage",
+                "Title/Usage/Detail",
+                "Title/Setext",
+                "Other",
+            ]
+        );
+        // a section lasts until the next heading of its level or above
+        let symbols = symbols(Path::new("README.md"), text);
+        assert_eq!(
+            symbols[1].range,
+            text.find("## Usage").unwrap()..text.find("Setext").unwrap()
+        );
+        assert_eq!(symbols[4].range.end, text.len());
+        assert!(symbols
+            .iter()
+            .map(|s| s.level)
+            .eq([1, 2, 3, 2, 1].map(Some)));
+    }
+
+    #[test]
+    fn skips_other_languages() {
+        assert!(outline_of("notes.txt", "# Title\n").is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{cbs, diagnostics};
+use fltk::{enums::*, prelude::*, *};
+use std::{
+    io::{BufRead, BufReader, Read},
+    path::Path,
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+// how often a run is checked for having exited
+const POLL: Duration = Duration::from_millis(50);
+
+// the process started by the last call to `spawn`, if it's still running
+static RUNNING: Mutex<Option<Arc<Mutex<Child>>>> = Mutex::new(None);
+// bumped by each `spawn`, so that the callbacks of the runs it replaced are dropped
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Adds the output tab to the bottom panel
+pub fn init(panel: &mut group::Tabs) {
+    panel.begin();
+    let mut disp = text::TextDisplay::default()
+        .with_label("Output")
+        .with_id("output");
+    disp.set_buffer(text::TextBuffer::default());
+    disp.set_text_font(Font::Courier);
+    disp.set_text_size(12);
+    disp.set_color(Color::Background2);
+    disp.set_text_color(Color::Foreground);
+    panel.end();
+    panel.auto_layout();
+}
+
+fn display() -> text::TextDisplay {
+    app::widget_from_id("output").unwrap()
+}
+
+/// Selects the output tab, showing the panel if it's hidden
+pub fn show() {
+    let mut panel: group::Tabs = app::widget_from_id("panel").unwrap();
+    panel.set_value(&display()).ok();
+    cbs::show_panel();
+    panel.redraw();
+}
+
+pub fn clear() {
+    display().buffer().unwrap().set_text("");
+}
+
+/// Appends `s` and scrolls to the end
+pub fn append(s: &str) {
+    let mut disp = display();
+    let mut buf = disp.buffer().unwrap();
+    buf.append(s);
+    let lines = buf.count_lines(0, buf.length());
+    disp.scroll(lines, 0);
+}
+
+fn shell(command: &str) -> Command {
+    let (sh, flag) = if cfg!(target_os = "windows") {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut cmd = Command::new(sh);
+    cmd.arg(flag).arg(command);
+    cmd
+}
+
+fn is_current(generation: u64) -> bool {
+    GENERATION.load(Ordering::SeqCst) == generation
+}
+
+fn read_lines(r: impl Read, on_line: Arc<Mutex<dyn FnMut(String) + Send>>, generation: u64) {
+    for line in BufReader::new(r).lines().map_while(Result::ok) {
+        let on_line = on_line.clone();
+        let mut line = Some(line);
+        app::awake_callback(move || {
+            if let Some(line) = line.take().filter(|_| is_current(generation)) {
+                (on_line.lock().unwrap())(line);
+            }
+        });
+    }
+}
+
+/// Kills the process started by `spawn`
+pub fn stop() {
+    if let Some(child) = RUNNING.lock().unwrap().take() {
+        child.lock().unwrap().kill().ok();
+    }
+}
+
+/// Runs `command` through the shell in `dir`, stopping the previous one.
+/// `on_line` gets each line of its output and `on_exit` its exit code,
+/// both on the main thread and only until another command is run
+pub fn spawn(
+    command: &str,
+    dir: &Path,
+    on_line: impl FnMut(String) + Send + 'static,
+    on_exit: impl FnOnce(Option<i32>) + Send + 'static,
+) -> std::io::Result<()> {
+    stop();
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let mut child = shell(command)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let child = Arc::new(Mutex::new(child));
+    *RUNNING.lock().unwrap() = Some(child.clone());
+    let on_line: Arc<Mutex<dyn FnMut(String) + Send>> = Arc::new(Mutex::new(on_line));
+    thread::spawn(move || {
+        let err = thread::spawn({
+            let on_line = on_line.clone();
+            move || read_lines(stderr, on_line, generation)
+        });
+        read_lines(stdout, on_line, generation);
+        err.join().ok();
+        // without holding the lock across the wait, which `stop` needs
+        let status = loop {
+            match child.lock().unwrap().try_wait() {
+                Ok(None) => (),
+                status => break status.ok().flatten(),
+            }
+            thread::sleep(POLL);
+        };
+        let mut running = RUNNING.lock().unwrap();
+        if running.as_ref().map(|c| Arc::ptr_eq(c, &child)) == Some(true) {
+            *running = None;
+        }
+        drop(running);
+        let mut on_exit = Some(on_exit);
+        app::awake_callback(move || {
+            if let Some(f) = on_exit.take().filter(|_| is_current(generation)) {
+                f(status.and_then(|s| s.code()));
+            }
+        });
+    });
+    Ok(())
+}
+
+/// Runs `command` in `dir`, showing its output in the output tab
+/// and collecting the problems it reports
+pub fn run(command: &str, dir: &Path) {
+    clear();
+    show();
+    append(&format!("> {}\n", command));
+    let found = Arc::new(Mutex::new(vec![]));
+    let mut parser = diagnostics::TextParser::new(dir);
+    let res = spawn(
+        command,
+        dir,
+        {
+            let found = found.clone();
+            move |line| {
+                found.lock().unwrap().extend(parser.parse_line(&line));
+                append(&format!("{}\n", line));
+            }
+        },
+        move |code| {
+            match code {
+                Some(code) => append(&format!("\n[exited with code {}]\n", code)),
+                None => append("\n[terminated]\n"),
+            }
+            diagnostics::publish("build", std::mem::take(&mut *found.lock().unwrap()));
+        },
+    );
+    if let Err(e) = res {
+        append(&format!("Failed to run: {}\n", e));
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    cbs,
+    diagnostics::{self, Diagnostic},
+    dialogs, output,
+    state::STATE,
+};
+use fltk::{enums::*, prelude::*, *};
+use std::sync::{Arc, Mutex};
+
+// the diagnostics in the order they're listed
+static LISTED: Mutex<Vec<Diagnostic>> = Mutex::new(Vec::new());
+
+/// Adds the problems tab to the bottom panel
+pub fn init(panel: &mut group::Tabs) {
+    panel.begin();
+    let mut b = browser::HoldBrowser::default()
+        .with_label("Problems")
+        .with_id("problems");
+    b.set_column_widths(&[24, 220]);
+    b.set_column_char('\t');
+    b.set_color(Color::Background2);
+    b.set_selection_color(Color::Selection);
+    b.set_text_size(12);
+    b.set_callback(|b| {
+        if b.value() > 0 {
+            open(b.value() as usize - 1);
+        }
+    });
+    panel.end();
+    panel.auto_layout();
+}
+
+fn browser() -> browser::HoldBrowser {
+    app::widget_from_id("problems").unwrap()
+}
+
+/// Lists the current diagnostics
+pub fn refresh() {
+    let (all, root) = STATE.with(|s| (s.diagnostics.all(), s.current_dir.clone()));
+    let mut b = browser();
+    b.clear();
+    for d in &all {
+        let path = d.path.strip_prefix(&root).unwrap_or(&d.path);
+        let message = d.message.lines().next().unwrap_or_default();
+        b.add(&format!(
+            "@C{}@.{}\t@.{}:{}:{}\t@.{}",
+            d.severity.color().bits(),
+            d.severity.icon(),
+            path.display(),
+            d.line,
+            d.col,
+            message
+        ));
+    }
+    let (errors, others) = all.iter().fold((0, 0), |(e, o), d| match d.severity {
+        diagnostics::Severity::Error => (e + 1, o),
+        _ => (e, o + 1),
+    });
+    b.set_label(&if all.is_empty() {
+        "Problems".to_string()
+    } else {
+        format!("Problems ({}/{})", errors, others)
+    });
+    *LISTED.lock().unwrap() = all;
+    if let Some(mut panel) = b.parent() {
+        panel.redraw();
+    }
+}
+
+/// Opens the file of the `idx`th listed problem at its position
+fn open(idx: usize) {
+    let d = LISTED.lock().unwrap().get(idx).cloned();
+    if let Some(d) = d {
+        if d.path.exists() {
+            dialogs::open_at(&d.path, d.line as i32, d.col as i32);
+        }
+    }
+}
+
+/// Selects the next or previous problem, wrapping around
+fn walk(forward: bool) {
+    let mut b = browser();
+    let count = b.size();
+    if count == 0 {
+        return;
+    }
+    let next = match (b.value(), forward) {
+        (0, true) => 1,
+        (0, false) => count,
+        (v, true) => v % count + 1,
+        (v, false) => (v + count - 2) % count + 1,
+    };
+    b.select(next);
+    b.middle_line(next);
+    open(next as usize - 1);
+}
+
+pub fn next() {
+    walk(true);
+}
+
+pub fn previous() {
+    walk(false);
+}
+
+/// Shows the problems tab, showing the panel if it's hidden
+pub fn show() {
+    let mut panel: group::Tabs = app::widget_from_id("panel").unwrap();
+    panel.set_value(&browser()).ok();
+    cbs::show_panel();
+    panel.redraw();
+}
+
+/// Runs `cargo build` in the project root, collecting its diagnostics
+pub fn check() {
+    let root = STATE.with(|s| s.current_dir.clone());
+    if !root.join("Cargo.toml").exists() {
+        dialog::message_default("Run a build task in the output panel to collect its problems");
+        return;
+    }
+    let command = "cargo build --message-format=json";
+    output::clear();
+    output::append(&format!("> {}\n", command));
+    show();
+    let found = Arc::new(Mutex::new(vec![]));
+    let res = output::spawn(
+        command,
+        &root,
+        {
+            let root = root.clone();
+            let found = found.clone();
+            move |line| match diagnostics::parse_cargo_json(&root, &line) {
+                Some((d, rendered)) => {
+                    output::append(&rendered);
+                    found.lock().unwrap().extend(d);
+                }
+                // build progress and anything else that's not json
+                None if !line.starts_with('{') => output::append(&format!("{}\n", line)),
+                None => (),
+            }
+        },
+        move |_| {
+            let found = std::mem::take(&mut *found.lock().unwrap());
+            if found.is_empty() {
+                output::append("\nNo problems found\n");
+            }
+            diagnostics::publish("build", found);
+        },
+    );
+    if let Err(e) = res {
+        output::append(&format!("Failed to run: {}\n", e));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{cbs, dialogs, state::STATE};
+use fltk::{enums::*, prelude::*, *};
+use std::{path::PathBuf, sync::Mutex};
+
+/// A place where a symbol is used, with a 1-based line and col
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub path: PathBuf,
+    pub line: usize,
+    pub col: usize,
+    /// The trimmed text of the line
+    pub text: String,
+}
+
+// the references in the order they're listed
+static LISTED: Mutex<Vec<Reference>> = Mutex::new(Vec::new());
+
+/// Adds the references tab to the bottom panel
+pub fn init(panel: &mut group::Tabs) {
+    panel.begin();
+    let mut b = browser::HoldBrowser::default()
+        .with_label("References")
+        .with_id("references");
+    b.set_column_widths(&[240]);
+    b.set_column_char('\t');
+    b.set_color(Color::Background2);
+    b.set_selection_color(Color::Selection);
+    b.set_text_size(12);
+    b.set_callback(|b| {
+        if b.value() > 0 {
+            open(b.value() as usize - 1);
+        }
+    });
+    panel.end();
+    panel.auto_layout();
+}
+
+fn browser() -> browser::HoldBrowser {
+    app::widget_from_id("references").unwrap()
+}
+
+/// Lists `found` and shows the references tab
+pub fn set(mut found: Vec<Reference>) {
+    found.sort_by(|a, b| (&a.path, a.line, a.col).cmp(&(&b.path, b.line, b.col)));
+    let root = STATE.with(|s| s.current_dir.clone());
+    let mut b = browser();
+    b.clear();
+    for r in &found {
+        let path = r.path.strip_prefix(&root).unwrap_or(&r.path);
+        b.add(&format!(
+            "@.{}:{}:{}\t@C{}@.{}",
+            path.display(),
+            r.line,
+            r.col,
+            Color::Foreground.darker().bits(),
+            r.text
+        ));
+    }
+    b.set_label(&format!("References ({})", found.len()));
+    *LISTED.lock().unwrap() = found;
+    let mut panel: group::Tabs = app::widget_from_id("panel").unwrap();
+    panel.set_value(&b).ok();
+    cbs::show_panel();
+    panel.redraw();
+}
+
+fn open(idx: usize) {
+    let r = LISTED.lock().unwrap().get(idx).cloned();
+    if let Some(r) = r {
+        dialogs::open_at(&r.path, r.line as i32, r.col as i32);
+    }
+}
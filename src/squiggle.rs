@@ -0,0 +1,179 @@
+use crate::diagnostics::{Diagnostic, Severity};
+use fltk::{enums::*, prelude::*, *};
+
+/// The style buffer of an editor, whose `count` styles, starting at 'A',
+/// are followed by their error and warning underlined variants
+#[derive(Debug, Clone)]
+pub struct Styles {
+    sbuf: text::TextBuffer,
+    count: usize,
+}
+
+impl Styles {
+    pub fn install(
+        ed: &mut text::TextEditor,
+        sbuf: text::TextBuffer,
+        entries: Vec<text::StyleTableEntry>,
+    ) -> Self {
+        let count = entries.len();
+        let mut table = vec![];
+        for attr in [
+            text::TextAttr::None,
+            text::TextAttr::Spelling,
+            text::TextAttr::Grammar,
+        ] {
+            table.extend(entries.iter().map(|e| text::StyleTableEntryExt {
+                color: e.color,
+                font: e.font,
+                size: e.size,
+                attr,
+                bgcolor: Color::Background2,
+            }));
+        }
+        ed.set_highlight_data_ext(sbuf.clone(), table);
+        Self { sbuf, count }
+    }
+
+    /// For editors without syntax highlighting
+    pub fn plain(ed: &mut text::TextEditor) -> Self {
+        let entry = text::StyleTableEntry {
+            color: Color::Foreground,
+            font: ed.text_font(),
+            size: ed.text_size(),
+        };
+        Self::install(ed, text::TextBuffer::default(), vec![entry])
+    }
+
+    /// Underlines the ranges of `diagnostics` in `text`, removing the previous ones
+    pub fn apply(&mut self, text: &str, diagnostics: &[Diagnostic]) {
+        let count = self.count as u8;
+        let mut style = self.sbuf.text().into_bytes();
+        // buffers that aren't highlighted only follow the text's length
+        style.resize(text.len(), b'A');
+        // the most severe diagnostic of each byte, overlapping ones don't add up
+        let mut marks: Vec<Option<Severity>> = vec![None; text.len()];
+        let starts: Vec<_> = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        for d in diagnostics {
+            let Some(start) = offset(text, &starts, d.line, d.col) else {
+                continue;
+            };
+            let end = offset(text, &starts, d.end_line, d.end_col)
+                .filter(|&end| end > start)
+                .unwrap_or_else(|| word_end(text, start));
+            for m in &mut marks[start..end] {
+                *m = Some(m.map_or(d.severity, |s| s.min(d.severity)));
+            }
+        }
+        for ((b, mark), c) in style.iter_mut().zip(marks).zip(text.bytes()) {
+            let base = b.saturating_sub(b'A') % count;
+            let variant = match mark {
+                _ if c == b'\n' => 0,
+                None => 0,
+                Some(Severity::Error) => 1,
+                Some(_) => 2,
+            };
+            *b = b'A' + base + variant * count;
+        }
+        self.sbuf.set_text(&String::from_utf8_lossy(&style));
+    }
+}
+
+/// The byte offset of the 1-based `line` and `col`
+fn offset(text: &str, starts: &[usize], line: usize, col: usize) -> Option<usize> {
+    let start = *starts.get(line.checked_sub(1)?)?;
+    let end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+    Some(
+        text[start..end]
+            .char_indices()
+            .nth(col.saturating_sub(1))
+            .map_or(end, |(i, _)| start + i),
+    )
+}
+
+/// The end of the word at `start`, covering at least one character
+fn word_end(text: &str, start: usize) -> usize {
+    let rest = &text[start..];
+    match rest.chars().next() {
+        Some(c) if c.is_whitespace() => start + c.len_utf8(),
+        Some(_) => start + rest.find(char::is_whitespace).unwrap_or(rest.len()),
+        None => start,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn diagnostic(line: usize, col: usize, end_col: usize, severity: Severity) -> Diagnostic {
+        Diagnostic {
+            path: PathBuf::from("a.rs"),
+            line,
+            col,
+            end_line: line,
+            end_col,
+            severity,
+            message: String::new(),
+        }
+    }
+
+    fn apply(base: &str, text: &str, diagnostics: &[Diagnostic]) -> String {
+        let mut sbuf = text::TextBuffer::default();
+        sbuf.set_text(base);
+        let mut styles = Styles { sbuf, count: 3 };
+        styles.apply(text, diagnostics);
+        styles.sbuf.text()
+    }
+
+    #[test]
+    fn underlines_by_severity() {
+        let d = [
+            diagnostic(1, 1, 3, Severity::Error),
+            diagnostic(2, 2, 3, Severity::Warning),
+        ];
+        // errors shift by one table, warnings by two
+        assert_eq!(apply("ABCACBA", "abc\ndef", &d), "DECACHA");
+        assert_eq!(apply("ABCACBA", "abc\ndef", &[]), "ABCACBA");
+    }
+
+    #[test]
+    fn overlapping_ranges_dont_stack() {
+        let d = [
+            diagnostic(1, 1, 4, Severity::Warning),
+            diagnostic(1, 1, 4, Severity::Warning),
+            diagnostic(1, 2, 3, Severity::Error),
+            diagnostic(1, 2, 3, Severity::Error),
+        ];
+        let style = apply("AAAA", "abcd", &d);
+        assert_eq!(style, "GDGA");
+        // reapplying starts over from the base styles
+        let mut sbuf = text::TextBuffer::default();
+        sbuf.set_text(&style);
+        let mut styles = Styles { sbuf, count: 3 };
+        styles.apply("abcd", &d);
+        styles.apply("abcd", &d);
+        assert_eq!(styles.sbuf.text(), "GDGA");
+        assert_eq!(apply(&style, "abcd", &[]), "AAAA");
+    }
+
+    #[test]
+    fn spans_multibyte_chars() {
+        // é takes 2 bytes, 😀 takes 4
+        let text = "é😀x\ny";
+        let d = [diagnostic(1, 2, 3, Severity::Error)];
+        let style = apply("", text, &d);
+        assert_eq!(style.len(), text.len());
+        assert_eq!(style, "AADDDDAAA");
+        // a missing end covers the word
+        let d = [diagnostic(1, 1, 1, Severity::Warning)];
+        assert_eq!(apply("", text, &d), "GGGGGGGAA");
+        // out of range positions are skipped or clamped to the line
+        let d = [
+            diagnostic(5, 1, 2, Severity::Error),
+            diagnostic(2, 1, 9, Severity::Error),
+        ];
+        assert_eq!(apply("", text, &d), "AAAAAAAAD");
+    }
+}
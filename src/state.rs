@@ -1,11 +1,22 @@
 #![allow(dead_code)]
 
-use crate::gui;
-use fltk::{app, group, prelude::*, text, utils::oncelock::Lazy};
+#[cfg(feature = "highlight")]
+use crate::outline;
+use crate::{
+    config::Config,
+    diagnostics::Diagnostics,
+    fbr, git, gui, gutter,
+    history::{History, HistoryKind},
+    lsp, navigation,
+};
+use fltk::{app, enums::Color, group, prelude::*, text, utils::oncelock::Lazy};
 use std::collections::HashMap;
 use std::{
-    path::PathBuf,
-    sync::atomic::{AtomicU32, Ordering},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
 };
 
 static COUNT: AtomicU32 = AtomicU32::new(0);
@@ -16,39 +27,76 @@ pub struct MyBuffer {
     pub id: String,
     pub buf: text::TextBuffer,
     pub current_file: Option<PathBuf>,
+    /// None for read-only tabs
+    pub gutter: Option<Arc<Mutex<gutter::Gutter>>>,
+    /// Blame diffs and compare tabs, which commands mustn't edit
+    pub read_only: bool,
 }
 
 pub struct State {
     pub map: HashMap<usize, MyBuffer>,
     pub current_dir: PathBuf,
+    pub search_history: History,
+    pub replace_history: History,
+    pub config: Config,
+    pub git: git::StatusMap,
+    pub diagnostics: Diagnostics,
 }
 
 impl State {
     pub fn new(current_dir: PathBuf) -> Self {
         let map = HashMap::default();
-        State { map, current_dir }
+        State {
+            map,
+            current_dir,
+            search_history: History::load(HistoryKind::Search),
+            replace_history: History::load(HistoryKind::Replace),
+            config: Config::load(),
+            git: git::StatusMap::default(),
+            diagnostics: Diagnostics::default(),
+        }
+    }
+    pub fn history(&mut self, kind: HistoryKind) -> &mut History {
+        match kind {
+            HistoryKind::Search => &mut self.search_history,
+            HistoryKind::Replace => &mut self.replace_history,
+        }
     }
     pub fn append(&mut self, current_path: Option<PathBuf>) {
         let mut tabs: group::Tabs = app::widget_from_id("tabs").unwrap();
         let mut open = false;
         let mut edid = 0;
+        // a path that doesn't exist yet is saved there, like an untitled buffer
+        let current_file = current_path.map(|p| p.canonicalize().unwrap_or(p));
         for (k, v) in &self.map {
-            if v.current_file == current_path {
+            if v.current_file == current_file {
                 open = true;
                 edid = *k;
                 break;
             }
         }
         if !open {
-            let old_count = COUNT.load(Ordering::Relaxed);
-            let id = format!("edrow{}", old_count);
-            COUNT.store(old_count + 1, Ordering::Relaxed);
-            let ed = gui::create_ed(&mut tabs, &id, &current_path);
+            let id = next_id();
+            let (mut ed, styles) = gui::create_ed(&mut tabs, &id, &current_file);
+            let gutter = gutter::attach(&mut ed, current_file.as_deref(), styles);
+            let mut buf = ed.buffer().unwrap();
+            #[cfg(feature = "highlight")]
+            outline::attach(&mut buf);
+            if let Some(p) = current_file.as_deref() {
+                gutter
+                    .lock()
+                    .unwrap()
+                    .set_diagnostics(self.diagnostics.for_file(p), &buf.text());
+                lsp::attach(&mut buf, p, &self.config, &self.current_dir);
+                navigation::attach(&ed);
+            }
             let mybuf = MyBuffer {
                 modified: false,
                 id,
-                buf: ed.buffer().unwrap(),
-                current_file: current_path.map(|p| p.canonicalize().unwrap()),
+                buf,
+                current_file,
+                gutter: Some(gutter),
+                read_only: false,
             };
             self.map.insert(ed.as_widget_ptr() as usize, mybuf);
         } else {
@@ -61,6 +109,28 @@ impl State {
             .ok();
             tabs.set_damage(true);
         }
+        // can't touch the state from within a state callback
+        app::add_timeout3(0.01, |_| fbr::follow_active());
+    }
+    /// Opens `text` in a new tab that can't be edited or saved
+    pub fn append_read_only(&mut self, title: &str, text: &str) {
+        let mut tabs: group::Tabs = app::widget_from_id("tabs").unwrap();
+        let id = next_id();
+        let (mut ed, _) = gui::create_ed(&mut tabs, &id, &None);
+        gui::set_read_only(&mut ed);
+        let mut buf = ed.buffer().unwrap();
+        buf.set_text(text);
+        ed.parent().unwrap().set_label(&format!("\t{}", title));
+        let mybuf = MyBuffer {
+            modified: false,
+            id,
+            buf,
+            current_file: None,
+            gutter: None,
+            read_only: true,
+        };
+        self.map.insert(ed.as_widget_ptr() as usize, mybuf);
+        tabs.redraw();
     }
     pub fn current_id(&self) -> Option<usize> {
         let tabs: group::Tabs = app::widget_from_id("tabs").unwrap();
@@ -73,24 +143,38 @@ impl State {
             .map(|ed| ed.as_widget_ptr() as usize)
     }
     pub fn was_modified(&mut self, flag: bool) {
-        let mut tabs: group::Tabs = app::widget_from_id("tabs").unwrap();
-        if tabs.children() == 0 {
-            return;
-        }
-        let mut edrow = tabs.value().unwrap();
-        if let Some(c) = edrow.child(0) {
-            let id = c.as_widget_ptr() as usize;
-            let mybuf = self.map.get_mut(&id).unwrap();
-            mybuf.modified = flag;
-            if let Some(f) = mybuf.current_file.as_ref() {
-                if flag {
-                    edrow.set_label(&format!("\t{} *", f.file_name().unwrap().to_str().unwrap()));
-                } else {
-                    edrow.set_label(&format!("\t{}", f.file_name().unwrap().to_str().unwrap()));
-                }
-                tabs.redraw();
+        if let Some(id) = self.current_id() {
+            self.map.get_mut(&id).unwrap().modified = flag;
+            self.update_label(id);
+            let mut tabs: group::Tabs = app::widget_from_id("tabs").unwrap();
+            tabs.redraw();
+        }
+    }
+    /// Labels the tab of editor `id` with its file name, git status and modified flag
+    fn update_label(&self, id: usize) {
+        let mybuf = self.map.get(&id).unwrap();
+        if let Some(f) = mybuf.current_file.as_ref() {
+            let ed = text::TextEditor::from_dyn_widget_ptr(id as *mut _).unwrap();
+            let mut edrow = ed.parent().unwrap();
+            let mut label = format!("\t{}", f.file_name().unwrap().to_str().unwrap());
+            let status = self.git.get(f);
+            if let Some(status) = status {
+                label.push(' ');
+                label.push_str(status.badge());
             }
+            if mybuf.modified {
+                label.push_str(" *");
+            }
+            edrow.set_label(&label);
+            edrow.set_label_color(status.map(|s| s.color()).unwrap_or(Color::Foreground));
+        }
+    }
+    pub fn update_labels(&self) {
+        for id in self.map.keys() {
+            self.update_label(*id);
         }
+        let mut tabs: group::Tabs = app::widget_from_id("tabs").unwrap();
+        tabs.redraw();
     }
     pub fn modified(&self) -> bool {
         if let Some(current_id) = self.current_id() {
@@ -122,6 +206,66 @@ impl State {
             mybuf.current_file = Some(path)
         }
     }
+    /// Points the tabs of `old`, or of the files under it, to `new`
+    pub fn rename_path(&mut self, old: &Path, new: &Path) {
+        for v in self.map.values_mut() {
+            let nf = match v.current_file.as_ref().map(|f| f.strip_prefix(old)) {
+                Some(Ok(rest)) if rest.as_os_str().is_empty() => new.to_path_buf(),
+                Some(Ok(rest)) => new.join(rest),
+                _ => continue,
+            };
+            v.current_file = Some(nf);
+        }
+        lsp::rename(old, new);
+        self.update_labels();
+    }
+    /// The open files with git markers, and whether their blame is shown
+    pub fn gutter_files(&self) -> Vec<(PathBuf, bool)> {
+        self.map
+            .values()
+            .filter_map(|v| {
+                let gutter = v.gutter.as_ref()?.lock().unwrap();
+                Some((v.current_file.clone()?, gutter.is_blame_shown()))
+            })
+            .collect()
+    }
+    /// Rediffs the open buffers against their files at HEAD, as read in `heads`
+    pub fn update_gutters(&self, heads: &[gutter::Head]) {
+        for (id, v) in &self.map {
+            let (Some(gutter), Some(path)) = (v.gutter.as_ref(), v.current_file.as_deref()) else {
+                continue;
+            };
+            // files opened since the HEAD versions were read are up to date
+            let Some(head) = heads.iter().find(|h| h.path == path) else {
+                continue;
+            };
+            gutter.lock().unwrap().set_head(head.clone(), &v.buf.text());
+            let mut ed = text::TextEditor::from_dyn_widget_ptr(*id as *mut _).unwrap();
+            ed.redraw();
+        }
+    }
+    /// Shows the latest diagnostics of every open file
+    pub fn update_diagnostics(&self) {
+        for (id, v) in &self.map {
+            let (Some(gutter), Some(path)) = (v.gutter.as_ref(), v.current_file.as_deref()) else {
+                continue;
+            };
+            gutter
+                .lock()
+                .unwrap()
+                .set_diagnostics(self.diagnostics.for_file(path), &v.buf.text());
+            let mut ed = text::TextEditor::from_dyn_widget_ptr(*id as *mut _).unwrap();
+            ed.redraw();
+        }
+    }
+    /// The current editor, unless its tab is read-only
+    pub fn editable_editor(&self) -> Option<text::TextEditor> {
+        let id = self.current_id()?;
+        if self.map.get(&id)?.read_only {
+            return None;
+        }
+        self.current_editor()
+    }
     pub fn current_editor(&self) -> Option<text::TextEditor> {
         let tabs: group::Tabs = app::widget_from_id("tabs").unwrap();
         if tabs.children() == 0 {
@@ -134,10 +278,12 @@ impl State {
     }
 }
 
+pub fn next_id() -> String {
+    format!("edrow{}", COUNT.fetch_add(1, Ordering::Relaxed))
+}
+
 pub static STATE: Lazy<app::GlobalState<State>> = Lazy::new(app::GlobalState::<State>::get);
 
-pub fn init_state(current_file: Option<PathBuf>, current_path: PathBuf) {
-    let mut state = State::new(current_path);
-    state.append(current_file);
-    app::GlobalState::new(state);
+pub fn init_state(current_path: PathBuf) {
+    app::GlobalState::new(State::new(current_path));
 }
@@ -0,0 +1,171 @@
+use crate::{dialogs, output, state::STATE};
+use fltk::{enums::*, prelude::*, *};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[cfg(feature = "term")]
+use crate::terminal;
+
+// the last task that was run
+static LAST: Mutex<Option<Task>> = Mutex::new(None);
+// menu paths of the tasks currently in the menu bar
+static MENU_ITEMS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    /// A terminal named after the task
+    #[default]
+    Terminal,
+    /// The output tab of the bottom panel
+    Panel,
+}
+
+/// A named command, as defined in `.red/tasks.toml`:
+///
+/// ```toml
+/// [[tasks]]
+/// name = "test foo"
+/// command = "cargo test -p foo"
+/// cwd = "crates/foo"
+/// output = "panel"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Task {
+    pub name: String,
+    pub command: String,
+    /// Relative to the project root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    #[serde(default)]
+    pub output: Output,
+}
+
+impl Task {
+    fn new(name: &str, command: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            command: command.to_string(),
+            cwd: None,
+            output: Output::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TasksFile {
+    #[serde(default)]
+    tasks: Vec<Task>,
+}
+
+fn tasks_file(root: &Path) -> PathBuf {
+    root.join(".red").join("tasks.toml")
+}
+
+fn cargo_defaults(root: &Path) -> Vec<Task> {
+    if !root.join("Cargo.toml").exists() {
+        return vec![];
+    }
+    vec![
+        Task::new("cargo build", "cargo build"),
+        Task::new("cargo check", "cargo check"),
+        Task::new("cargo clippy", "cargo clippy"),
+        Task::new("cargo test", "cargo test"),
+        Task::new("cargo run", "cargo run"),
+    ]
+}
+
+/// The tasks of the project file, followed by the detected defaults it doesn't override
+pub fn load(root: &Path) -> Vec<Task> {
+    let mut tasks = fs::read_to_string(tasks_file(root))
+        .ok()
+        .and_then(|s| match toml::from_str::<TasksFile>(&s) {
+            Ok(f) => Some(f.tasks),
+            Err(e) => {
+                eprintln!("Invalid tasks file: {}", e);
+                None
+            }
+        })
+        .unwrap_or_default();
+    for task in cargo_defaults(root) {
+        if !tasks.iter().any(|t| t.name == task.name) {
+            tasks.push(task);
+        }
+    }
+    tasks
+}
+
+pub fn run(task: &Task) {
+    *LAST.lock().unwrap() = Some(task.clone());
+    let root = STATE.with(|s| s.current_dir.clone());
+    let dir = match task.cwd.as_ref() {
+        Some(cwd) => root.join(cwd),
+        None => root,
+    };
+    match task.output {
+        #[cfg(feature = "term")]
+        Output::Terminal => terminal::run_task(&task.name, &task.command, &dir),
+        _ => output::run(&task.command, &dir),
+    }
+}
+
+pub fn rerun() {
+    let last = LAST.lock().unwrap().clone();
+    match last {
+        Some(task) => run(&task),
+        None => dialog::message_default("No task was run yet"),
+    }
+}
+
+/// Opens the project's tasks file, creating it from the defaults if needed
+pub fn configure() {
+    let root = STATE.with(|s| s.current_dir.clone());
+    let path = tasks_file(&root);
+    if !path.exists() {
+        let mut tasks = cargo_defaults(&root);
+        if tasks.is_empty() {
+            tasks.push(Task::new("build", "make"));
+        }
+        let res = fs::create_dir_all(path.parent().unwrap()).and_then(|_| {
+            let s = toml::to_string_pretty(&TasksFile { tasks }).unwrap_or_default();
+            fs::write(&path, s)
+        });
+        if let Err(e) = res {
+            dialog::alert_default(&format!("Failed to create {}: {}", path.display(), e));
+            return;
+        }
+    }
+    STATE.with(move |s| s.append(Some(path.clone())));
+}
+
+/// Adds a menu item per task under the Tasks menu
+pub fn init_menu(m: &mut impl MenuExt) {
+    let root = STATE.with(|s| s.current_dir.clone());
+    let mut items = MENU_ITEMS.lock().unwrap();
+    for path in items.drain(..) {
+        let idx = m.find_index(&path);
+        if idx >= 0 {
+            m.remove(idx);
+        }
+    }
+    for task in load(&root) {
+        let path = format!("&Tasks/{}\t", dialogs::menu_escape(&task.name));
+        m.add(&path, Shortcut::None, menu::MenuFlag::Normal, move |_| {
+            run(&task)
+        });
+        items.push(path);
+    }
+}
+
+/// Reloads the Tasks menu when the tasks file was saved
+pub fn saved(path: &Path) {
+    let root = STATE.with(|s| s.current_dir.clone());
+    if path == tasks_file(&root) {
+        let mut m: menu::SysMenuBar = app::widget_from_id("menu").unwrap();
+        init_menu(&mut m);
+    }
+}
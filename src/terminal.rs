@@ -0,0 +1,403 @@
+use crate::{cbs, commands, state::STATE, utils};
+use fltk::{enums::*, prelude::*, utils::oncelock::Lazy, *};
+use fltk_term::PPTerm;
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+static COUNT: AtomicU32 = AtomicU32::new(1);
+// the open terminals, each running its own shell
+static TERMS: Lazy<Mutex<Vec<Term>>> = Lazy::new(Mutex::default);
+
+struct Term {
+    term: PPTerm,
+    /// Roughly how many characters were typed, recalled or pasted on the shell's current line
+    typed: Arc<AtomicUsize>,
+    /// The shell's process, once found, unless it couldn't be told apart
+    shell: Arc<Mutex<Option<Shell>>>,
+}
+
+/// Every process with its parent and terminal, from `ps`
+fn processes() -> Vec<(u32, u32, String)> {
+    let Ok(out) = Command::new("ps")
+        .args(["-A", "-o", "pid=,ppid=,tty="])
+        .stderr(Stdio::null())
+        .output()
+    else {
+        return vec![];
+    };
+    String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter_map(|l| {
+            let mut cols = l.split_whitespace();
+            let pid = cols.next()?.parse().ok()?;
+            let ppid = cols.next()?.parse().ok()?;
+            Some((pid, ppid, cols.next()?.to_string()))
+        })
+        .collect()
+}
+
+/// A shell of ours, told apart by its terminal
+#[derive(Debug, Clone, PartialEq)]
+struct Shell {
+    pid: u32,
+    tty: String,
+}
+
+/// The editor's children attached to a terminal other than its own, i.e. terminal shells,
+/// since tasks and servers talk through pipes
+fn shells() -> Vec<Shell> {
+    let rows = processes();
+    let me = std::process::id();
+    let own = rows.iter().find(|r| r.0 == me).map(|r| r.2.clone());
+    rows.into_iter()
+        .filter(|(_, ppid, tty)| *ppid == me && !tty.starts_with('?') && Some(tty) != own.as_ref())
+        .map(|(pid, _, tty)| Shell { pid, tty })
+        .collect()
+}
+
+/// The processes with children, e.g. shells running a program, which gets their input
+fn busy_shells() -> Vec<u32> {
+    processes().into_iter().map(|p| p.1).collect()
+}
+
+/// Looks for the shell of a new terminal in the background, as the one no other terminal has
+fn find_shell(found: Arc<Mutex<Option<Shell>>>) {
+    thread::spawn(move || {
+        let claimed: Vec<_> = TERMS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|t| t.shell.lock().unwrap().clone())
+            .collect();
+        let mut new = shells().into_iter().filter(|s| !claimed.contains(s));
+        // two terminals opened at once can't be told apart
+        if let (Some(shell), None) = (new.next(), new.next()) {
+            *found.lock().unwrap() = Some(shell);
+        }
+    });
+}
+
+/// Ends the shell of `t`, like closing a terminal window does
+fn end(t: &Term) {
+    let shell = t.shell.lock().unwrap().clone();
+    match shell {
+        Some(shell) => {
+            thread::spawn(move || {
+                // unless it's gone already and its pid was reused
+                if shells().contains(&shell) {
+                    Command::new("kill")
+                        .args(["-HUP", &shell.pid.to_string()])
+                        .stderr(Stdio::null())
+                        .status()
+                        .ok();
+                }
+            });
+        }
+        None => {
+            let exit: &[u8] = if cfg!(target_os = "windows") {
+                b"exit\r\n"
+            } else {
+                b"exit\n"
+            };
+            t.term.write_all(exit).ok();
+        }
+    }
+}
+
+/// Keeps track of whether the user is in the middle of typing a command
+fn track_typing(term: &mut PPTerm, typed: Arc<AtomicUsize>) {
+    term.handle(move |_, ev| {
+        if ev == Event::KeyDown {
+            let key = app::event_key();
+            let ctrl = app::is_event_ctrl();
+            if key == Key::Enter
+                || key == Key::KPEnter
+                || (ctrl && (key == Key::from_char('c') || key == Key::from_char('u')))
+            {
+                typed.store(0, Ordering::Relaxed);
+            } else if key == Key::BackSpace {
+                typed
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                        Some(n.saturating_sub(1))
+                    })
+                    .ok();
+            } else if key == Key::Up || key == Key::Down {
+                // a command recalled from the history
+                typed.fetch_add(1, Ordering::Relaxed);
+            } else if !ctrl && !app::event_text().is_empty() {
+                typed.fetch_add(1, Ordering::Relaxed);
+            }
+        } else if ev == Event::Paste {
+            typed.fetch_add(1, Ordering::Relaxed);
+        }
+        // let the terminal handle it
+        false
+    });
+}
+
+pub fn init_popup_menu(m: &mut (impl MenuExt + 'static)) {
+    for cmd in commands::COMMANDS
+        .iter()
+        .filter(|c| c.id.starts_with("terminal."))
+    {
+        commands::add_item(m, cmd.id, cmd.label());
+    }
+}
+
+/// Adds a first terminal to the bottom panel
+pub fn init(tabs: &mut group::Tabs, mut popup: menu::MenuButton) {
+    tabs.handle(move |t, ev| match ev {
+        Event::Push
+            if app::event_mouse_button() == app::MouseButton::Right
+                && app::event_y() < t.y() + 30 =>
+        {
+            popup.popup();
+            true
+        }
+        _ => false,
+    });
+    let root = STATE.with(|s| s.current_dir.clone());
+    add(tabs, None, &root);
+}
+
+fn panel() -> group::Tabs {
+    app::widget_from_id("panel").unwrap()
+}
+
+/// Adds a terminal whose shell starts in `dir`
+fn add(tabs: &mut group::Tabs, name: Option<&str>, dir: &Path) {
+    tabs.begin();
+    let mut term = PPTerm::default();
+    let shell = Arc::new(Mutex::new(None));
+    // there's no `ps` to find it with on windows
+    if cfg!(unix) {
+        find_shell(shell.clone());
+    }
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => format!("Terminal {}", COUNT.fetch_add(1, Ordering::Relaxed)),
+    };
+    term.set_label(&format!("\t{}", name));
+    term.set_trigger(CallbackTrigger::Closed);
+    term.set_callback(|t| {
+        if app::callback_reason() == CallbackReason::Closed {
+            remove(t);
+        }
+    });
+    let typed = Arc::new(AtomicUsize::new(0));
+    track_typing(&mut term, typed.clone());
+    // PPTerm starts its shell in the editor's working directory
+    if utils::current_dir() != dir {
+        term.write_all(cd_command(dir).as_bytes()).ok();
+    }
+    tabs.end();
+    tabs.auto_layout();
+    tabs.set_value(&*term).ok();
+    tabs.redraw();
+    TERMS.lock().unwrap().push(Term { term, typed, shell });
+}
+
+/// Ends the shell of `w` and deletes its tab
+fn remove(w: &impl WidgetExt) {
+    let mut terms = TERMS.lock().unwrap();
+    let term = terms
+        .iter()
+        .position(|t| t.term.as_widget_ptr() == w.as_widget_ptr())
+        .map(|idx| terms.remove(idx));
+    drop(terms);
+    let mut tabs = panel();
+    tabs.remove(w);
+    tabs.auto_layout();
+    tabs.redraw();
+    if let Some(t) = term {
+        end(&t);
+        app::delete_widget((*t.term).clone());
+    }
+}
+
+fn is_terminal(w: &impl WidgetExt) -> bool {
+    TERMS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|t| t.term.as_widget_ptr() == w.as_widget_ptr())
+}
+
+/// Selects a terminal, creating one if there's none, and shows the panel
+fn show_terminal() {
+    let mut tabs = panel();
+    if !tabs.value().map(|w| is_terminal(&w)).unwrap_or(false) {
+        let selected = match TERMS.lock().unwrap().last() {
+            Some(t) => tabs.set_value(&*t.term).is_ok(),
+            None => false,
+        };
+        if !selected {
+            let root = STATE.with(|s| s.current_dir.clone());
+            add(&mut tabs, None, &root);
+        }
+    }
+    cbs::show_panel();
+}
+
+/// Runs `f` with the selected terminal
+fn with_active<R>(f: impl FnOnce(&Term) -> R) -> Option<R> {
+    let active = panel().value()?.as_widget_ptr();
+    let terms = TERMS.lock().unwrap();
+    terms
+        .iter()
+        .find(|t| t.term.as_widget_ptr() == active)
+        .map(f)
+}
+
+fn cd_command(dir: &Path) -> String {
+    let dir = dir.to_string_lossy();
+    if cfg!(target_os = "windows") {
+        format!("cd /d \"{}\"\r\n", dir)
+    } else {
+        // the leading space keeps it out of the shell's history
+        format!(" cd '{}'\n", dir.replace('\'', "'\\''"))
+    }
+}
+
+/// Changes the directory of the selected terminal if it's idle at a prompt,
+/// i.e. nothing was typed or recalled and its shell isn't running a program
+pub fn cd(dir: &Path) {
+    let shell = with_active(|t| {
+        let shell = t.shell.lock().unwrap().clone();
+        shell.filter(|_| t.typed.load(Ordering::Relaxed) == 0)
+    });
+    let Some(shell) = shell.flatten() else {
+        return;
+    };
+    let command = cd_command(dir);
+    thread::spawn(move || {
+        if busy_shells().contains(&shell.pid) {
+            return;
+        }
+        app::awake_callback(move || {
+            let terms = TERMS.lock().unwrap();
+            let idle = terms.iter().find(|t| {
+                t.typed.load(Ordering::Relaxed) == 0
+                    && t.shell.lock().unwrap().as_ref() == Some(&shell)
+            });
+            if let Some(t) = idle {
+                t.term.write_all(command.as_bytes()).ok();
+            }
+        });
+    });
+}
+
+/// Opens a new terminal in `dir`
+pub fn open_here(dir: &Path) {
+    add(&mut panel(), None, dir);
+    cbs::show_panel();
+}
+
+/// Follows the directories clicked in the file browser, if enabled
+pub fn follow_browser(dir: &Path) {
+    if STATE.with(|s| s.config.terminal_follows_browser) {
+        cd(dir);
+    }
+}
+
+pub fn toggle_follow_browser() {
+    let flag = STATE.with(|s| {
+        s.config.terminal_follows_browser = !s.config.terminal_follows_browser;
+        s.config.save();
+        s.config.terminal_follows_browser
+    });
+    commands::set_checked("terminal.follow_browser", flag);
+}
+
+pub fn new_terminal() {
+    let root = STATE.with(|s| s.current_dir.clone());
+    add(&mut panel(), None, &root);
+    cbs::show_panel();
+}
+
+pub fn close_terminal() {
+    if let Some(w) = panel().value().filter(is_terminal) {
+        remove(&w);
+    }
+}
+
+pub fn rename_terminal() {
+    if let Some(mut w) = panel().value().filter(is_terminal) {
+        let name = w.label().trim_start_matches('\t').to_string();
+        if let Some(name) = dialog::input_default("Terminal name", &name) {
+            if !name.trim().is_empty() {
+                w.set_label(&format!("\t{}", name.trim()));
+                panel().redraw();
+            }
+        }
+    }
+}
+
+/// Runs the selected text, or the current line, in the selected terminal
+pub fn send_selection() {
+    let text = STATE.with(|s| {
+        let buf = s.buf()?;
+        let selection = buf.selection_text();
+        if !selection.is_empty() {
+            Some(selection)
+        } else {
+            let ed = s.current_editor()?;
+            Some(buf.line_text(ed.insert_position()))
+        }
+    });
+    let Some(mut text) = text.filter(|t| !t.trim().is_empty()) else {
+        return;
+    };
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    if cfg!(target_os = "windows") {
+        text = text.replace("\r\n", "\n").replace('\n', "\r\n");
+    }
+    show_terminal();
+    with_active(|t| {
+        t.term.write_all(text.as_bytes()).ok();
+        t.typed.store(0, Ordering::Relaxed);
+    });
+}
+
+/// Runs `command` in `dir`, in a terminal named after the task
+pub fn run_task(name: &str, command: &str, dir: &Path) {
+    let mut tabs = panel();
+    let label = format!("\t{}", name);
+    let busy = busy_shells();
+    // reuse the task's terminal once its last run finished, unless something was typed in it
+    let selected = match TERMS.lock().unwrap().iter().find(|t| {
+        let shell = t.shell.lock().unwrap();
+        t.term.label() == label
+            && t.typed.load(Ordering::Relaxed) == 0
+            && shell.as_ref().is_some_and(|s| !busy.contains(&s.pid))
+    }) {
+        Some(t) => tabs.set_value(&*t.term).is_ok(),
+        None => false,
+    };
+    if !selected {
+        add(&mut tabs, Some(name), dir);
+    }
+    cbs::show_panel();
+    let line_end = if cfg!(target_os = "windows") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    with_active(|t| {
+        if selected {
+            t.term.write_all(cd_command(dir).as_bytes()).ok();
+        }
+        t.term
+            .write_all(format!("{}{}", command, line_end).as_bytes())
+            .ok();
+    });
+}
@@ -13,6 +13,18 @@ pub fn strip_unc_path(p: &Path) -> String {
     }
 }
 
+/// Where red keeps per-user data, e.g. ~/.config/red
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+        Some(PathBuf::from(dir))
+    } else {
+        env::var_os("HOME").map(|h| PathBuf::from(h).join(".config"))
+    };
+    base.map(|b| b.join("red"))
+}
+
 #[allow(dead_code)]
 pub fn has_program(prog: &str) -> bool {
     // hacky
@@ -45,8 +57,20 @@ pub fn init_args(args: env::Args) -> (Option<PathBuf>, PathBuf) {
         env::current_dir().unwrap()
     };
 
-    let current_path = env::current_dir().unwrap().canonicalize().unwrap();
-    (current_file, current_path)
+    (current_file, current_dir())
+}
+
+pub fn current_dir() -> PathBuf {
+    env::current_dir().unwrap().canonicalize().unwrap()
+}
+
+/// The files passed as `red --diff <old> <new>`
+pub fn diff_args(args: env::Args) -> Option<(PathBuf, PathBuf)> {
+    let args: Vec<_> = args.skip(1).collect();
+    match &args[..] {
+        [flag, old, new] if flag == "--diff" => Some((PathBuf::from(old), PathBuf::from(new))),
+        _ => None,
+    }
 }
 
 #[allow(dead_code)]
@@ -61,3 +85,80 @@ pub fn can_use_xterm() -> bool {
         false
     }
 }
+
+/// Lists the files under `root`, skipping `.git`, `target` and gitignored paths.
+pub fn project_files(root: &Path) -> Vec<PathBuf> {
+    const MAX_FILES: usize = 50_000;
+    ignore::WalkBuilder::new(root)
+        .hidden(false)
+        .filter_entry(|e| e.file_name() != ".git" && e.file_name() != "target")
+        .build()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .take(MAX_FILES)
+        .map(|e| e.into_path())
+        .collect()
+}
+
+/// Scores `cand` against the subsequence `pat`, case-insensitively.
+/// Consecutive matches, matches at word boundaries and in the file name score higher.
+pub fn fuzzy_score(pat: &str, cand: &str) -> Option<i32> {
+    let chars: Vec<char> = cand.chars().collect();
+    let name_start = cand
+        .rfind(['/', '\\'])
+        .map(|i| cand[..i + 1].chars().count())
+        .unwrap_or(0);
+    let mut score = 0;
+    let mut ci = 0;
+    let mut prev: Option<usize> = None;
+    for pc in pat.chars().filter(|c| !c.is_whitespace()) {
+        while ci < chars.len() && !chars[ci].to_lowercase().eq(pc.to_lowercase()) {
+            ci += 1;
+        }
+        if ci == chars.len() {
+            return None;
+        }
+        score += 1;
+        if ci > 0 && prev == Some(ci - 1) {
+            score += 5;
+        }
+        if ci == 0 || matches!(chars[ci - 1], '/' | '\\' | '_' | '-' | '.' | ' ') {
+            score += 3;
+        }
+        if ci >= name_start {
+            score += 2;
+        }
+        prev = Some(ci);
+        ci += 1;
+    }
+    Some(score * 10 - chars.len() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_score_matches_subsequences_ignoring_case() {
+        assert!(fuzzy_score("MnRs", "src/main.rs").is_some());
+        assert!(fuzzy_score("ma in", "main").is_some());
+        assert_eq!(fuzzy_score("xyz", "src/main.rs"), None);
+        assert_eq!(fuzzy_score("mainn", "main"), None);
+    }
+
+    #[test]
+    fn fuzzy_score_prefers_consecutive_matches() {
+        assert!(fuzzy_score("abc", "abcxxx") > fuzzy_score("abc", "axbxcx"));
+    }
+
+    #[test]
+    fn fuzzy_score_prefers_the_file_name() {
+        assert!(fuzzy_score("main", "src/main.rs") > fuzzy_score("main", "main/src.rs"));
+    }
+
+    #[test]
+    fn fuzzy_score_prefers_shorter_candidates() {
+        assert!(fuzzy_score("lib", "lib.rs") > fuzzy_score("lib", "lib.rs.bak"));
+        assert_eq!(fuzzy_score("", "abc"), Some(-3));
+    }
+}